@@ -25,6 +25,72 @@ fn main() {
 		build.compile("clocal");
 	}
 
+	let target = std::env::var("TARGET").unwrap_or_default();
+	if cfg!(feature = "setjmp") && std::env::var("HOST").is_ok_and(|host| host == target) {
+		// Build the C half of the setjmp tests (which run on the host), as
+		// Rust can't call setjmp itself
+		cc::Build::new()
+			.warnings(true)
+			.extra_warnings(true)
+			.flag("-std=c99")
+			.file("./src/setjmp_test.c")
+			.compile("setjmp_test");
+	}
+
+	// Tell setjmp which floating-point registers it has to save
+	println!("cargo:rustc-check-cfg=cfg(tinyrlibc_fpu, values(\"single\", \"double\"))");
+	if let Some(fpu) = fpu(&target) {
+		println!("cargo:rustc-cfg=tinyrlibc_fpu=\"{}\"", fpu);
+	}
+
+	// Tell exit how many functions atexit (and __cxa_atexit) can register
+	if cfg!(feature = "exit") {
+		println!("cargo:rustc-env=TINYRLIBC_ATEXIT_MAX={}", atexit_max());
+	}
+
 	println!("cargo:rerun-if-changed=build.rs");
 	println!("cargo:rerun-if-changed=src/snprintf.c");
+	println!("cargo:rerun-if-changed=src/setjmp_test.c");
+	println!("cargo:rerun-if-env-changed=TINYRLIBC_ATEXIT_MAX");
+}
+
+/// Get the number of functions `atexit` can register, from the
+/// `TINYRLIBC_ATEXIT_MAX` environment variable. C requires at least 32.
+fn atexit_max() -> usize {
+	let Ok(value) = std::env::var("TINYRLIBC_ATEXIT_MAX") else {
+		return 32;
+	};
+	match value.parse() {
+		Ok(max) if max >= 32 => max,
+		_ => panic!(
+			"TINYRLIBC_ATEXIT_MAX must be a number, at least 32 (not {:?})",
+			value
+		),
+	}
+}
+
+/// Work out whether a target passes floating-point values in registers (and
+/// so has callee-saved floating-point registers), and how wide they are.
+fn fpu(target: &str) -> Option<&'static str> {
+	let arch = target.split('-').next()?;
+	if arch.starts_with("thumb") || arch.starts_with("arm") {
+		// The Arm hard-float ABI saves d8-d15
+		target.ends_with("eabihf").then_some("double")
+	} else if let Some(extensions) = arch
+		.strip_prefix("riscv32")
+		.or_else(|| arch.strip_prefix("riscv64"))
+	{
+		// 'g' includes 'd'. Extensions with more than one letter come after an
+		// underscore.
+		let extensions = extensions.split('_').next()?;
+		if extensions.contains(['g', 'd']) {
+			Some("double")
+		} else if extensions.contains('f') {
+			Some("single")
+		} else {
+			None
+		}
+	} else {
+		None
+	}
 }
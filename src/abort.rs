@@ -0,0 +1,84 @@
+//! Rust implementation of the C standard library's `abort` function.
+//!
+//! With the `signal` feature, `abort` first raises `SIGABRT`. If that
+//! returns (or without the `signal` feature), it calls the abort hook set
+//! with [`tinyrlibc_set_abort_hook`] - which might execute an undefined
+//! instruction, or reset the chip - or by default, panics.
+//!
+//! Licensed under the Blue Oak Model Licence 1.0.0
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// A function for `abort` to finish with.
+///
+/// It must not return. If it does, `abort` panics.
+pub type AbortHook = unsafe extern "C" fn();
+
+/// The registered hook, or 0 for the default
+static HOOK: AtomicUsize = AtomicUsize::new(0);
+
+/// Set the function for `abort` to finish with, returning the previous one.
+///
+/// Passing NULL restores the default, which panics.
+#[cfg_attr(any(feature = "abort", feature = "signal"), no_mangle)]
+pub extern "C" fn tinyrlibc_set_abort_hook(hook: Option<AbortHook>) -> Option<AbortHook> {
+	let old = crate::hook::swap(&HOOK, hook.map_or(0, |hook| hook as usize));
+	// Safety: we only ever store valid hooks (or zero)
+	(old != 0).then(|| unsafe { core::mem::transmute::<usize, AbortHook>(old) })
+}
+
+/// Call the abort hook, panicking if there isn't one (or it returns).
+fn finish(hook: usize) -> ! {
+	if hook != 0 {
+		unsafe {
+			let hook: AbortHook = core::mem::transmute(hook);
+			hook();
+		}
+	}
+	// TODO: This should call core::intrinsics::abort() but that's unstable.
+	panic!("Aborted");
+}
+
+/// Rust implementation of the C standard library's `abort` function.
+///
+/// Raises `SIGABRT` (with the `signal` feature), even if it is blocked or
+/// ignored. If its handler returns, `SIG_DFL` is put back, and the abort hook
+/// is called. This never returns.
+#[cfg_attr(all(not(test), any(feature = "abort", feature = "signal")), no_mangle)]
+pub extern "C" fn abort() -> ! {
+	#[cfg(feature = "signal")]
+	crate::signal::raise_abort();
+	finish(HOOK.load(Ordering::Acquire))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn set_hook() {
+		let _guard = crate::test_lock();
+		extern "C" fn hook() {}
+		assert!(tinyrlibc_set_abort_hook(Some(hook)).is_none());
+		let old = tinyrlibc_set_abort_hook(None);
+		assert_eq!(
+			old.map(|hook| hook as usize),
+			Some(hook as *const fn() as usize)
+		);
+		assert!(tinyrlibc_set_abort_hook(None).is_none());
+	}
+
+	// `abort` can't unwind, so the end of it is tested directly
+	#[test]
+	#[should_panic(expected = "Aborted")]
+	fn default_panics() {
+		finish(0);
+	}
+
+	#[test]
+	#[should_panic(expected = "Aborted")]
+	fn returning_hook_panics() {
+		extern "C" fn hook() {}
+		finish(hook as *const fn() as usize);
+	}
+}
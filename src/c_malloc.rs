@@ -0,0 +1,140 @@
+//! A Rust global allocator which uses an external C `malloc` and `free`.
+//!
+//! This is the inverse of `malloc.rs`: it's for when something else (such as
+//! a vendor SDK) already owns the heap, and Rust should allocate from it too.
+//!
+//! Licensed under the Blue Oak Model Licence 1.0.0
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ffi::c_void;
+
+#[cfg(all(feature = "alloc", not(feature = "heap")))]
+compile_error!(
+	"`CMallocAllocator` can't be used with the `alloc` feature (unless `heap` is enabled), as our `malloc` would call it"
+);
+
+extern "C" {
+	fn malloc(size: usize) -> *mut c_void;
+	fn calloc(nmemb: usize, size: usize) -> *mut c_void;
+	fn realloc(ptr: *mut c_void, size: usize) -> *mut c_void;
+	fn free(ptr: *mut c_void);
+}
+
+/// The alignment we assume C `malloc` gives us.
+///
+/// This is 8 on 32-bit targets and 16 on 64-bit targets, which is what newlib
+/// and glibc provide.
+const MALLOC_ALIGN: usize = 2 * core::mem::size_of::<usize>();
+
+/// A Rust global allocator which uses the C library's `malloc` and `free`.
+///
+/// Allocations with a larger alignment than `malloc` guarantees are padded,
+/// and a pointer to the start of the underlying allocation is stored just
+/// before the pointer we return.
+///
+/// ```rust,ignore
+/// #[global_allocator]
+/// static ALLOCATOR: tinyrlibc::CMallocAllocator = tinyrlibc::CMallocAllocator;
+/// ```
+pub struct CMallocAllocator;
+
+impl CMallocAllocator {
+	/// Allocate with more alignment than `malloc` guarantees.
+	unsafe fn alloc_padded(&self, layout: Layout) -> *mut u8 {
+		// We need room to move the pointer up to the alignment, and to store
+		// the original pointer before it.
+		let Some(padded) = layout.size().checked_add(layout.align()) else {
+			return core::ptr::null_mut();
+		};
+		let raw = malloc(padded) as *mut u8;
+		if raw.is_null() {
+			return raw;
+		}
+		let word = core::mem::size_of::<usize>();
+		let offset = word + raw.add(word).align_offset(layout.align());
+		let aligned = raw.add(offset);
+		(aligned as *mut *mut u8).sub(1).write_unaligned(raw);
+		aligned
+	}
+}
+
+unsafe impl GlobalAlloc for CMallocAllocator {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		if layout.align() <= MALLOC_ALIGN {
+			malloc(layout.size()) as *mut u8
+		} else {
+			self.alloc_padded(layout)
+		}
+	}
+
+	unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+		if layout.align() <= MALLOC_ALIGN {
+			calloc(1, layout.size()) as *mut u8
+		} else {
+			let ptr = self.alloc_padded(layout);
+			if !ptr.is_null() {
+				core::ptr::write_bytes(ptr, 0, layout.size());
+			}
+			ptr
+		}
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		if layout.align() <= MALLOC_ALIGN {
+			free(ptr as *mut c_void)
+		} else {
+			let raw = (ptr as *mut *mut u8).sub(1).read_unaligned();
+			free(raw as *mut c_void)
+		}
+	}
+
+	unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+		if layout.align() <= MALLOC_ALIGN {
+			return realloc(ptr as *mut c_void, new_size) as *mut u8;
+		}
+		let Ok(new_layout) = Layout::from_size_align(new_size, layout.align()) else {
+			return core::ptr::null_mut();
+		};
+		let new_ptr = self.alloc_padded(new_layout);
+		if !new_ptr.is_null() {
+			core::ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+			self.dealloc(ptr, layout);
+		}
+		new_ptr
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn small_alignment() {
+		let layout = Layout::from_size_align(100, 8).unwrap();
+		let ptr = unsafe { CMallocAllocator.alloc_zeroed(layout) };
+		assert!(!ptr.is_null());
+		assert_eq!(unsafe { *ptr.add(99) }, 0);
+		unsafe { core::ptr::write_bytes(ptr, 0xAA, 100) };
+		let ptr = unsafe { CMallocAllocator.realloc(ptr, layout, 1000) };
+		assert!(!ptr.is_null());
+		assert_eq!(unsafe { *ptr.add(99) }, 0xAA);
+		unsafe { CMallocAllocator.dealloc(ptr, Layout::from_size_align(1000, 8).unwrap()) };
+	}
+
+	#[test]
+	fn large_alignment() {
+		for align in [32, 64, 4096] {
+			let layout = Layout::from_size_align(40, align).unwrap();
+			let ptr = unsafe { CMallocAllocator.alloc_zeroed(layout) };
+			assert!(!ptr.is_null());
+			assert_eq!(ptr as usize % align, 0);
+			assert_eq!(unsafe { *ptr.add(39) }, 0);
+			unsafe { core::ptr::write_bytes(ptr, 0x55, 40) };
+			let ptr = unsafe { CMallocAllocator.realloc(ptr, layout, 200) };
+			assert!(!ptr.is_null());
+			assert_eq!(ptr as usize % align, 0);
+			assert_eq!(unsafe { *ptr.add(39) }, 0x55);
+			unsafe { CMallocAllocator.dealloc(ptr, Layout::from_size_align(200, align).unwrap()) };
+		}
+	}
+}
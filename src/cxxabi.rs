@@ -0,0 +1,370 @@
+//! The parts of the C++ ABI runtime that C++ code compiled with
+//! `-fno-exceptions -fno-rtti` still needs.
+//!
+//! This gives destructors for static objects (`__cxa_atexit`,
+//! `__cxa_finalize` and `__dso_handle`), pure virtual calls
+//! (`__cxa_pure_virtual`), thread-safe initialisation of function-local
+//! statics (`__cxa_guard_acquire` and friends) and `operator new` and
+//! `operator delete`, which use `malloc` and `free`.
+//!
+//! Destructors are kept with the functions registered with `atexit`, so they
+//! are run by `exit`.
+//!
+//! Licensed under the Blue Oak Model Licence 1.0.0
+
+use core::ffi::{c_int, c_void};
+
+use portable_atomic::{AtomicU8, Ordering};
+
+use crate::abort::abort;
+use crate::exit::{finalize_cxa, register_cxa};
+
+#[cfg(feature = "alloc")]
+use crate::malloc::{free, malloc};
+
+#[cfg(not(feature = "alloc"))]
+extern "C" {
+	fn malloc(size: usize) -> *mut u8;
+	fn free(ptr: *mut u8);
+}
+
+/// A destructor, as registered with `__cxa_atexit`.
+pub type CxaDestructor = unsafe extern "C" fn(object: *mut c_void);
+
+/// The type of [`__dso_handle`], which points to itself.
+#[repr(transparent)]
+pub struct DsoHandle(*const DsoHandle);
+
+// Safety: it only ever points to itself
+unsafe impl Sync for DsoHandle {}
+
+/// Identifies this program (as opposed to a shared library) to
+/// `__cxa_atexit` and `__cxa_finalize`. Only its address matters.
+#[allow(non_upper_case_globals)]
+#[cfg_attr(not(test), no_mangle)]
+pub static __dso_handle: DsoHandle = DsoHandle(&__dso_handle);
+
+/// Register a destructor for a static object, to be called with `object` by
+/// `exit` (or by `__cxa_finalize`, for `dso`).
+///
+/// Returns 0, or -1 if [`ATEXIT_MAX`](crate::ATEXIT_MAX) functions have already
+/// been registered, counting those registered with `atexit`. Programs with
+/// many static objects may need a larger `TINYRLIBC_ATEXIT_MAX`.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn __cxa_atexit(
+	destructor: CxaDestructor,
+	object: *mut c_void,
+	dso: *mut c_void,
+) -> c_int {
+	if register_cxa(destructor, object, dso) {
+		0
+	} else {
+		-1
+	}
+}
+
+/// The Arm EABI's version of [`__cxa_atexit`], which GCC calls on Arm
+/// targets. Note the different argument order.
+#[cfg_attr(all(not(test), target_arch = "arm"), no_mangle)]
+pub extern "C" fn __aeabi_atexit(
+	object: *mut c_void,
+	destructor: CxaDestructor,
+	dso: *mut c_void,
+) -> c_int {
+	__cxa_atexit(destructor, object, dso)
+}
+
+/// Call the destructors registered for `dso`, most recent first, and forget
+/// them. If `dso` is NULL, call everything registered with `__cxa_atexit`
+/// and `atexit`.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn __cxa_finalize(dso: *mut c_void) {
+	finalize_cxa(dso);
+}
+
+/// Called if a pure virtual function is called (which can only happen while
+/// an object is being constructed or destroyed). Calls `abort`.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn __cxa_pure_virtual() -> ! {
+	abort()
+}
+
+/// The guard variable for a function-local static.
+///
+/// The Arm EABI uses 32 bits, and everything else 64.
+#[cfg(target_arch = "arm")]
+#[allow(non_camel_case_types)]
+pub type __guard = u32;
+
+/// The guard variable for a function-local static.
+///
+/// The Arm EABI uses 32 bits, and everything else 64.
+#[cfg(not(target_arch = "arm"))]
+#[allow(non_camel_case_types)]
+pub type __guard = u64;
+
+/// The byte of a guard which the compiler checks (and we set to 1) once the
+/// static is initialised. The Arm EABI checks bit 0 of the word; everyone
+/// else checks the first byte.
+const DONE: usize = if cfg!(all(target_arch = "arm", target_endian = "big")) {
+	3
+} else {
+	0
+};
+
+/// The byte of a guard we set while the static is being initialised.
+const BUSY: usize = DONE ^ 1;
+
+/// Get a byte of a guard.
+///
+/// # Safety
+///
+/// `guard` must be valid, and only accessed atomically.
+unsafe fn guard_byte<'a>(guard: *mut __guard, index: usize) -> &'a AtomicU8 {
+	&*guard.cast::<AtomicU8>().add(index)
+}
+
+/// Start initialising a function-local static.
+///
+/// Returns 1 if the caller should initialise it (and then call
+/// `__cxa_guard_release`, or `__cxa_guard_abort` if that fails), or 0 if it
+/// is already initialised. If another thread is initialising it, waits until
+/// that thread is done.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn __cxa_guard_acquire(guard: *mut __guard) -> c_int {
+	let done = guard_byte(guard, DONE);
+	let busy = guard_byte(guard, BUSY);
+	loop {
+		if done.load(Ordering::Acquire) != 0 {
+			return 0;
+		}
+		if busy
+			.compare_exchange_weak(0, 1, Ordering::Acquire, Ordering::Relaxed)
+			.is_ok()
+		{
+			// Someone else may have finished just before we took it
+			if done.load(Ordering::Acquire) != 0 {
+				busy.store(0, Ordering::Release);
+				return 0;
+			}
+			return 1;
+		}
+		core::hint::spin_loop();
+	}
+}
+
+/// Mark a function-local static as initialised.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn __cxa_guard_release(guard: *mut __guard) {
+	guard_byte(guard, DONE).store(1, Ordering::Release);
+	guard_byte(guard, BUSY).store(0, Ordering::Release);
+}
+
+/// Give up initialising a function-local static, so the next caller of
+/// `__cxa_guard_acquire` tries again.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn __cxa_guard_abort(guard: *mut __guard) {
+	guard_byte(guard, BUSY).store(0, Ordering::Release);
+}
+
+/// `operator new(size_t)`
+///
+/// There are no exceptions to throw, so this calls `abort` if there isn't
+/// enough memory.
+#[cfg_attr(all(not(test), target_pointer_width = "32"), export_name = "_Znwj")]
+#[cfg_attr(all(not(test), target_pointer_width = "64"), export_name = "_Znwm")]
+pub unsafe extern "C" fn operator_new(size: usize) -> *mut c_void {
+	let ptr = operator_new_nothrow(size, core::ptr::null());
+	if ptr.is_null() {
+		abort();
+	}
+	ptr
+}
+
+/// `operator new[](size_t)`
+///
+/// There are no exceptions to throw, so this calls `abort` if there isn't
+/// enough memory.
+#[cfg_attr(all(not(test), target_pointer_width = "32"), export_name = "_Znaj")]
+#[cfg_attr(all(not(test), target_pointer_width = "64"), export_name = "_Znam")]
+pub unsafe extern "C" fn operator_new_array(size: usize) -> *mut c_void {
+	operator_new(size)
+}
+
+/// `operator new(size_t, const std::nothrow_t&)`
+///
+/// Returns NULL if there isn't enough memory.
+#[cfg_attr(
+	all(not(test), target_pointer_width = "32"),
+	export_name = "_ZnwjRKSt9nothrow_t"
+)]
+#[cfg_attr(
+	all(not(test), target_pointer_width = "64"),
+	export_name = "_ZnwmRKSt9nothrow_t"
+)]
+pub unsafe extern "C" fn operator_new_nothrow(size: usize, _tag: *const c_void) -> *mut c_void {
+	// Every object must have its own address, even if it is empty
+	malloc(size.max(1)).cast()
+}
+
+/// `operator new[](size_t, const std::nothrow_t&)`
+///
+/// Returns NULL if there isn't enough memory.
+#[cfg_attr(
+	all(not(test), target_pointer_width = "32"),
+	export_name = "_ZnajRKSt9nothrow_t"
+)]
+#[cfg_attr(
+	all(not(test), target_pointer_width = "64"),
+	export_name = "_ZnamRKSt9nothrow_t"
+)]
+pub unsafe extern "C" fn operator_new_array_nothrow(
+	size: usize,
+	tag: *const c_void,
+) -> *mut c_void {
+	operator_new_nothrow(size, tag)
+}
+
+/// `operator delete(void*)`
+#[cfg_attr(not(test), export_name = "_ZdlPv")]
+pub unsafe extern "C" fn operator_delete(ptr: *mut c_void) {
+	free(ptr.cast());
+}
+
+/// `operator delete[](void*)`
+#[cfg_attr(not(test), export_name = "_ZdaPv")]
+pub unsafe extern "C" fn operator_delete_array(ptr: *mut c_void) {
+	free(ptr.cast());
+}
+
+/// `operator delete(void*, size_t)`
+#[cfg_attr(all(not(test), target_pointer_width = "32"), export_name = "_ZdlPvj")]
+#[cfg_attr(all(not(test), target_pointer_width = "64"), export_name = "_ZdlPvm")]
+pub unsafe extern "C" fn operator_delete_sized(ptr: *mut c_void, _size: usize) {
+	free(ptr.cast());
+}
+
+/// `operator delete[](void*, size_t)`
+#[cfg_attr(all(not(test), target_pointer_width = "32"), export_name = "_ZdaPvj")]
+#[cfg_attr(all(not(test), target_pointer_width = "64"), export_name = "_ZdaPvm")]
+pub unsafe extern "C" fn operator_delete_array_sized(ptr: *mut c_void, _size: usize) {
+	free(ptr.cast());
+}
+
+/// `operator delete(void*, const std::nothrow_t&)`
+#[cfg_attr(not(test), export_name = "_ZdlPvRKSt9nothrow_t")]
+pub unsafe extern "C" fn operator_delete_nothrow(ptr: *mut c_void, _tag: *const c_void) {
+	free(ptr.cast());
+}
+
+/// `operator delete[](void*, const std::nothrow_t&)`
+#[cfg_attr(not(test), export_name = "_ZdaPvRKSt9nothrow_t")]
+pub unsafe extern "C" fn operator_delete_array_nothrow(ptr: *mut c_void, _tag: *const c_void) {
+	free(ptr.cast());
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::cell::RefCell;
+	use std::sync::atomic::AtomicUsize;
+
+	std::thread_local! {
+		/// The objects which have been destroyed, in order
+		static DESTROYED: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+	}
+
+	fn destroyed() -> Vec<usize> {
+		DESTROYED.with(|destroyed| destroyed.take())
+	}
+
+	extern "C" fn destroy(object: *mut c_void) {
+		DESTROYED.with(|destroyed| destroyed.borrow_mut().push(object as usize));
+	}
+
+	extern "C" fn plain() {
+		DESTROYED.with(|destroyed| destroyed.borrow_mut().push(0));
+	}
+
+	fn dso_handle() -> *mut c_void {
+		&__dso_handle as *const DsoHandle as *mut c_void
+	}
+
+	#[test]
+	fn finalize_dso() {
+		let _guard = crate::test_lock();
+		let other = 0x1000 as *mut c_void;
+		assert_eq!(__cxa_atexit(destroy, 1 as *mut c_void, dso_handle()), 0);
+		assert_eq!(__cxa_atexit(destroy, 2 as *mut c_void, other), 0);
+		assert_eq!(crate::atexit(plain), 0);
+		assert_eq!(__aeabi_atexit(3 as *mut c_void, destroy, dso_handle()), 0);
+
+		__cxa_finalize(dso_handle());
+		assert_eq!(destroyed(), [3, 1]);
+		// The rest are run in reverse order, along with `atexit` functions
+		__cxa_finalize(core::ptr::null_mut());
+		assert_eq!(destroyed(), [0, 2]);
+		__cxa_finalize(core::ptr::null_mut());
+		assert_eq!(destroyed(), []);
+	}
+
+	#[test]
+	fn dso_handle_points_to_itself() {
+		assert_eq!(__dso_handle.0 as *mut c_void, dso_handle());
+	}
+
+	#[test]
+	fn guard() {
+		let mut guard: __guard = 0;
+		unsafe {
+			assert_eq!(__cxa_guard_acquire(&mut guard), 1);
+			__cxa_guard_abort(&mut guard);
+			assert_eq!(__cxa_guard_acquire(&mut guard), 1);
+			__cxa_guard_release(&mut guard);
+			assert_eq!(__cxa_guard_acquire(&mut guard), 0);
+		}
+		// This is what the compiler checks before calling us
+		assert_eq!(guard & 1, 1);
+	}
+
+	#[test]
+	fn guard_initialises_once() {
+		struct Guard(core::cell::UnsafeCell<__guard>);
+		unsafe impl Sync for Guard {}
+		impl Guard {
+			fn get(&self) -> *mut __guard {
+				self.0.get()
+			}
+		}
+		let guard = Guard(core::cell::UnsafeCell::new(0));
+		let count = AtomicUsize::new(0);
+		std::thread::scope(|scope| {
+			for _ in 0..8 {
+				scope.spawn(|| unsafe {
+					if __cxa_guard_acquire(guard.get()) == 1 {
+						count.fetch_add(1, Ordering::Relaxed);
+						std::thread::yield_now();
+						__cxa_guard_release(guard.get());
+					}
+				});
+			}
+		});
+		assert_eq!(count.load(Ordering::Relaxed), 1);
+	}
+
+	#[test]
+	fn new_and_delete() {
+		let _guard = crate::test_lock();
+		unsafe {
+			let a = operator_new(16);
+			let b = operator_new_array(0);
+			let c = operator_new_nothrow(4, core::ptr::null());
+			assert!(!a.is_null() && !b.is_null() && !c.is_null());
+			assert_ne!(a, b);
+			a.cast::<u8>().write_bytes(0xAA, 16);
+			operator_delete_sized(a, 16);
+			operator_delete_array(b);
+			operator_delete_nothrow(c, core::ptr::null());
+		}
+	}
+}
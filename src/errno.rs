@@ -0,0 +1,73 @@
+//! Rust implementation of the C library's `errno`.
+//!
+//! C headers define `errno` as a macro which calls a function to find the
+//! current error number: `__errno` in newlib, and `__errno_location` in
+//! glibc. We provide both. There is only one error number, as bare-metal
+//! systems don't have threads.
+//!
+//! Licensed under the Blue Oak Model Licence 1.0.0
+
+use core::ffi::c_int;
+
+/// Out of memory
+pub const ENOMEM: c_int = 12;
+
+/// Invalid argument
+pub const EINVAL: c_int = 22;
+
+/// Result too large
+pub const ERANGE: c_int = 34;
+
+/// Illegal byte sequence (newlib's value - Linux uses 84)
+pub const EILSEQ: c_int = 138;
+
+/// The error number.
+static mut ERRNO: c_int = 0;
+
+/// Get a pointer to the error number.
+fn errno_location() -> *mut c_int {
+	// Older compilers need `unsafe` to take the address of a static mut
+	#[allow(unused_unsafe)]
+	unsafe {
+		core::ptr::addr_of_mut!(ERRNO)
+	}
+}
+
+/// Get the current error number.
+pub fn errno() -> c_int {
+	unsafe { errno_location().read_volatile() }
+}
+
+/// Set the current error number.
+pub fn set_errno(value: c_int) {
+	unsafe { errno_location().write_volatile(value) }
+}
+
+/// Rust implementation of the newlib function `__errno`, which the `errno`
+/// macro calls.
+#[cfg_attr(all(not(test), feature = "errno"), no_mangle)]
+pub extern "C" fn __errno() -> *mut c_int {
+	errno_location()
+}
+
+/// Rust implementation of the glibc function `__errno_location`, which the
+/// `errno` macro calls.
+#[cfg_attr(all(not(test), feature = "errno"), no_mangle)]
+pub extern "C" fn __errno_location() -> *mut c_int {
+	errno_location()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn set_and_get() {
+		let _guard = crate::test_lock();
+		set_errno(ENOMEM);
+		assert_eq!(errno(), ENOMEM);
+		assert_eq!(unsafe { *__errno() }, ENOMEM);
+		unsafe { *__errno_location() = ERANGE };
+		assert_eq!(errno(), ERANGE);
+	}
+}
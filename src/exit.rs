@@ -0,0 +1,364 @@
+//! Rust implementation of the C standard library functions `exit`, `_Exit`,
+//! `atexit`, `quick_exit` and `at_quick_exit`.
+//!
+//! There is nothing to exit to on bare metal, so once the handlers have run,
+//! `exit` calls the hook set with [`tinyrlibc_set_exit_hook`] - which might
+//! reset the chip, or park the CPU - or by default, panics.
+//!
+//! With the `cxxabi` feature, C++ destructors registered with `__cxa_atexit`
+//! go in the same list as `atexit` functions, so they all run in reverse order
+//! of registration.
+//!
+//! Licensed under the Blue Oak Model Licence 1.0.0
+
+use core::ffi::c_int;
+#[cfg(feature = "cxxabi")]
+use core::ffi::c_void;
+
+use crate::spinlock::SpinLock;
+
+/// The number of functions which can be registered with `atexit` (and,
+/// separately, with `at_quick_exit`). C++ destructors registered with
+/// `__cxa_atexit` take `atexit` slots too.
+///
+/// This is 32, the least C allows, unless the `TINYRLIBC_ATEXIT_MAX`
+/// environment variable is set to a larger number when building.
+pub const ATEXIT_MAX: usize = parse_decimal(env!("TINYRLIBC_ATEXIT_MAX"));
+
+/// Parse a decimal number (which `build.rs` has checked), at compile time.
+const fn parse_decimal(digits: &str) -> usize {
+	let digits = digits.as_bytes();
+	let mut value = 0;
+	let mut idx = 0;
+	while idx < digits.len() {
+		value = value * 10 + (digits[idx] - b'0') as usize;
+		idx += 1;
+	}
+	value
+}
+
+/// The status for `exit` which means success
+pub const EXIT_SUCCESS: c_int = 0;
+
+/// The status for `exit` which means failure
+pub const EXIT_FAILURE: c_int = 1;
+
+/// A function registered with `atexit` or `at_quick_exit`.
+pub type ExitHandler = unsafe extern "C" fn();
+
+/// A function for `exit` to call once the `atexit` handlers have run, to
+/// flush any buffered output (such as a UART or RTT channel).
+pub type FlushHook = unsafe extern "C" fn();
+
+/// A function for `exit`, `quick_exit` and `_Exit` to finish with.
+///
+/// It is given the exit status, and must not return.
+pub type ExitHook = unsafe extern "C" fn(status: c_int) -> !;
+
+/// A registered function.
+#[derive(Clone, Copy)]
+enum Handler {
+	/// From `atexit` or `at_quick_exit`
+	Plain(ExitHandler),
+	/// From `__cxa_atexit`, with its argument and DSO handle
+	#[cfg(feature = "cxxabi")]
+	Cxa {
+		func: unsafe extern "C" fn(*mut c_void),
+		arg: *mut c_void,
+		dso: *mut c_void,
+	},
+}
+
+impl Handler {
+	unsafe fn call(self) {
+		match self {
+			Handler::Plain(func) => func(),
+			#[cfg(feature = "cxxabi")]
+			Handler::Cxa { func, arg, .. } => func(arg),
+		}
+	}
+}
+
+/// A stack of registered handlers.
+struct Handlers {
+	list: [Option<Handler>; ATEXIT_MAX],
+	len: usize,
+}
+
+impl Handlers {
+	const fn new() -> Handlers {
+		Handlers {
+			list: [None; ATEXIT_MAX],
+			len: 0,
+		}
+	}
+
+	/// Add a handler, returning `false` if there is no room.
+	fn push(&mut self, handler: Handler) -> bool {
+		let Some(slot) = self.list.get_mut(self.len) else {
+			return false;
+		};
+		*slot = Some(handler);
+		self.len += 1;
+		true
+	}
+
+	/// Take the most recently added handler.
+	fn pop(&mut self) -> Option<Handler> {
+		self.len = self.len.checked_sub(1)?;
+		self.list[self.len].take()
+	}
+
+	/// Take the most recently added `__cxa_atexit` handler for `dso`.
+	#[cfg(feature = "cxxabi")]
+	fn pop_dso(&mut self, dso: *mut c_void) -> Option<Handler> {
+		let index = self.list[..self.len].iter().rposition(
+			|handler| matches!(handler, Some(Handler::Cxa { dso: d, .. }) if *d == dso),
+		)?;
+		let handler = self.list[index].take();
+		self.list[index..self.len].rotate_left(1);
+		self.len -= 1;
+		handler
+	}
+}
+
+/// The registered handlers and hooks.
+struct State {
+	at_exit: Handlers,
+	at_quick_exit: Handlers,
+	flush: Option<FlushHook>,
+	exit: Option<ExitHook>,
+}
+
+impl State {
+	const EMPTY: State = State {
+		at_exit: Handlers::new(),
+		at_quick_exit: Handlers::new(),
+		flush: None,
+		exit: None,
+	};
+}
+
+/// The registered handlers and hooks
+static STATE: SpinLock<State> = SpinLock::new(State::EMPTY);
+
+/// Run `f` with the state locked.
+///
+/// `f` must not call any of the handlers or hooks, as they may register
+/// more.
+fn with_state<R>(f: impl FnOnce(&mut State) -> R) -> R {
+	STATE.with(f)
+}
+
+/// Forget every handler and hook, between tests.
+#[cfg(test)]
+pub(crate) fn reset() {
+	with_state(|state| *state = State::EMPTY);
+}
+
+/// Run and remove each handler in a list, most recently registered first.
+///
+/// Handlers registered while this runs are run too.
+fn run_handlers(list: impl Fn(&mut State) -> &mut Handlers) {
+	while let Some(handler) = with_state(|state| list(state).pop()) {
+		unsafe { handler.call() };
+	}
+}
+
+/// Register a C++ destructor for `exit` to call, as `__cxa_atexit` does.
+///
+/// Returns `false` if [`ATEXIT_MAX`] functions have already been registered.
+#[cfg(feature = "cxxabi")]
+pub(crate) fn register_cxa(
+	func: unsafe extern "C" fn(*mut c_void),
+	arg: *mut c_void,
+	dso: *mut c_void,
+) -> bool {
+	with_state(|state| state.at_exit.push(Handler::Cxa { func, arg, dso }))
+}
+
+/// Run and remove the C++ destructors registered for `dso`, most recent
+/// first, as `__cxa_finalize` does. A NULL `dso` runs everything registered
+/// with `atexit` too.
+#[cfg(feature = "cxxabi")]
+pub(crate) fn finalize_cxa(dso: *mut c_void) {
+	if dso.is_null() {
+		run_handlers(|state| &mut state.at_exit);
+		return;
+	}
+	while let Some(handler) = with_state(|state| state.at_exit.pop_dso(dso)) {
+		unsafe { handler.call() };
+	}
+}
+
+/// Call the exit hook, panicking if there isn't one.
+fn finish(hook: Option<ExitHook>, status: c_int) -> ! {
+	match hook {
+		Some(hook) => unsafe { hook(status) },
+		None => panic!("Exited with status {}", status),
+	}
+}
+
+/// Set the function for `exit`, `quick_exit` and `_Exit` to finish with,
+/// returning the previous one.
+///
+/// Passing NULL restores the default, which panics.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn tinyrlibc_set_exit_hook(hook: Option<ExitHook>) -> Option<ExitHook> {
+	with_state(|state| core::mem::replace(&mut state.exit, hook))
+}
+
+/// Set the function for `exit` to call to flush buffered output, returning
+/// the previous one.
+///
+/// To flush more than one output, the new hook can call the old one.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn tinyrlibc_set_flush_hook(hook: Option<FlushHook>) -> Option<FlushHook> {
+	with_state(|state| core::mem::replace(&mut state.flush, hook))
+}
+
+/// Rust implementation of the C standard library's `atexit` function.
+///
+/// Registers `func` to be called by `exit`. Returns 0, or -1 if
+/// [`ATEXIT_MAX`] functions have already been registered.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn atexit(func: ExitHandler) -> c_int {
+	if with_state(|state| state.at_exit.push(Handler::Plain(func))) {
+		0
+	} else {
+		-1
+	}
+}
+
+/// Rust implementation of the C standard library's `at_quick_exit` function.
+///
+/// Registers `func` to be called by `quick_exit`. Returns 0, or -1 if
+/// [`ATEXIT_MAX`] functions have already been registered.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn at_quick_exit(func: ExitHandler) -> c_int {
+	if with_state(|state| state.at_quick_exit.push(Handler::Plain(func))) {
+		0
+	} else {
+		-1
+	}
+}
+
+/// Rust implementation of the C standard library's `exit` function.
+///
+/// Calls the functions registered with `atexit`, most recent first, then
+/// the flush hook, then the exit hook.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn exit(status: c_int) -> ! {
+	run_handlers(|state| &mut state.at_exit);
+	if let Some(flush) = with_state(|state| state.flush) {
+		unsafe { flush() };
+	}
+	_Exit(status)
+}
+
+/// Rust implementation of the C standard library's `quick_exit` function.
+///
+/// Calls the functions registered with `at_quick_exit`, most recent first,
+/// then the exit hook. Output is not flushed.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn quick_exit(status: c_int) -> ! {
+	run_handlers(|state| &mut state.at_quick_exit);
+	_Exit(status)
+}
+
+/// Rust implementation of the C standard library's `_Exit` function.
+///
+/// Calls the exit hook straight away, without running any handlers or
+/// flushing output.
+#[allow(non_snake_case)]
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn _Exit(status: c_int) -> ! {
+	finish(with_state(|state| state.exit), status)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::cell::RefCell;
+
+	std::thread_local! {
+		/// The handlers which have been called, in order
+		static CALLED: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+	}
+
+	fn called() -> Vec<u8> {
+		CALLED.with(|called| called.take())
+	}
+
+	extern "C" fn first() {
+		CALLED.with(|called| called.borrow_mut().push(1));
+	}
+
+	extern "C" fn second() {
+		CALLED.with(|called| called.borrow_mut().push(2));
+	}
+
+	extern "C" fn registers_more() {
+		CALLED.with(|called| called.borrow_mut().push(3));
+		assert_eq!(atexit(first), 0);
+	}
+
+	#[test]
+	fn runs_in_reverse_order() {
+		let _guard = crate::test_lock();
+		assert_eq!(atexit(first), 0);
+		assert_eq!(atexit(second), 0);
+		assert_eq!(atexit(registers_more), 0);
+		assert_eq!(at_quick_exit(second), 0);
+		run_handlers(|state| &mut state.at_exit);
+		assert_eq!(called(), [3, 1, 2, 1]);
+		// Each handler only runs once
+		run_handlers(|state| &mut state.at_exit);
+		assert_eq!(called(), []);
+
+		run_handlers(|state| &mut state.at_quick_exit);
+		assert_eq!(called(), [2]);
+	}
+
+	#[test]
+	fn fills_up() {
+		let _guard = crate::test_lock();
+		for _ in 0..ATEXIT_MAX {
+			assert_eq!(atexit(first), 0);
+		}
+		assert_eq!(atexit(second), -1);
+		assert_eq!(at_quick_exit(second), 0);
+		run_handlers(|state| &mut state.at_exit);
+		assert_eq!(called(), [1; ATEXIT_MAX]);
+		assert_eq!(atexit(second), 0);
+	}
+
+	#[test]
+	fn hooks() {
+		let _guard = crate::test_lock();
+		extern "C" fn exit_hook(_status: c_int) -> ! {
+			unreachable!()
+		}
+		assert!(tinyrlibc_set_exit_hook(Some(exit_hook)).is_none());
+		let old = tinyrlibc_set_exit_hook(None);
+		assert_eq!(
+			old.map(|hook| hook as usize),
+			Some(exit_hook as *const fn(c_int) as usize)
+		);
+		assert!(tinyrlibc_set_flush_hook(Some(first)).is_none());
+		assert!(tinyrlibc_set_flush_hook(None).is_some());
+	}
+
+	#[test]
+	fn parses_atexit_max() {
+		assert_eq!(parse_decimal("32"), 32);
+		assert_eq!(parse_decimal("1024"), 1024);
+	}
+
+	// `exit` can't unwind, so the end of it is tested directly
+	#[test]
+	#[should_panic(expected = "Exited with status 3")]
+	fn default_panics() {
+		finish(None, 3);
+	}
+}
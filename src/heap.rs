@@ -0,0 +1,840 @@
+//! A self-contained heap allocator.
+//!
+//! This lets `malloc` and friends work without a Rust `#[global_allocator]`.
+//! It can also be used as the Rust global allocator (see [`TinyHeap`]), so
+//! that Rust and C share one heap.
+//!
+//! Free blocks are kept in segregated lists, one per power-of-two size class,
+//! and adjacent free blocks are coalesced using boundary tags.
+//!
+//! The heap is protected by a spin lock, so it can be shared between threads
+//! but not with interrupt handlers. If an interrupt handler allocated (or
+//! freed) while the code it interrupted held the lock, it would spin forever,
+//! so `malloc` and friends must not be called from interrupt context.
+//!
+//! Licensed under the Blue Oak Model Licence 1.0.0
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::null_mut;
+
+use portable_atomic::{AtomicBool, Ordering};
+
+use crate::spinlock::SpinLock;
+
+/// The size of a block header (and footer)
+const WORD: usize = core::mem::size_of::<usize>();
+
+/// Every block is a multiple of this size, and every payload is aligned to it
+const GRANULE: usize = 2 * WORD;
+
+/// The smallest block we can make - a header, two list pointers and a footer
+const MIN_BLOCK: usize = 2 * GRANULE;
+
+/// Header flag: this block is in use
+const USED: usize = 1;
+
+/// Header flag: the previous block is in use (so has no footer)
+const PREV_USED: usize = 2;
+
+/// All the header flags
+const FLAGS: usize = USED | PREV_USED;
+
+/// One free list for each power-of-two size class
+const NUM_BINS: usize = usize::BITS as usize;
+
+/// The list pointers stored in the payload of a free block
+#[repr(C)]
+struct FreeBlock {
+	header: usize,
+	next: *mut FreeBlock,
+	prev: *mut FreeBlock,
+}
+
+/// A heap, managing one contiguous region of memory.
+///
+/// Each block starts with a one-word header holding its size and flags. Free
+/// blocks also end with a footer holding their size, so that `free` can find
+/// and merge with a free block that comes before it. The region ends with a
+/// zero-sized "used" block, so we never merge past the end.
+///
+/// A region which starts where the last one ended (as memory from `sbrk`
+/// does) is joined on to it, replacing its end marker, so blocks can span
+/// both.
+pub struct Heap {
+	/// The head of the free list for each size class
+	bins: [*mut FreeBlock; NUM_BINS],
+	/// Bit `n` is set if `bins[n]` is not empty
+	bitmap: usize,
+	/// The number of bytes in blocks that are currently free
+	free_bytes: usize,
+	/// The end marker of the most recently added region, or NULL
+	last_marker: *mut u8,
+	/// The address just past the most recently added region
+	last_end: usize,
+}
+
+impl Heap {
+	/// Create a heap with no memory in it.
+	pub const fn empty() -> Heap {
+		Heap {
+			bins: [null_mut(); NUM_BINS],
+			bitmap: 0,
+			free_bytes: 0,
+			last_marker: null_mut(),
+			last_end: 0,
+		}
+	}
+
+	/// Give the heap a region of memory to manage.
+	///
+	/// Any memory the heap previously managed is forgotten. Regions too small
+	/// to hold a block are ignored.
+	///
+	/// # Safety
+	///
+	/// The region must be valid for reads and writes, and must not be used for
+	/// anything else for as long as the heap exists.
+	pub unsafe fn init(&mut self, start: *mut u8, size: usize) {
+		*self = Heap::empty();
+		self.extend(start, size);
+	}
+
+	/// Give the heap another region of memory to manage, as well as any it
+	/// already has.
+	///
+	/// If the region starts just after the last one, the two are joined.
+	/// Otherwise, regions too small to hold a block are ignored.
+	///
+	/// # Safety
+	///
+	/// The region must be valid for reads and writes, and must not be used for
+	/// anything else for as long as the heap exists.
+	pub unsafe fn extend(&mut self, start: *mut u8, size: usize) {
+		let start_addr = start as usize;
+		let Some(end_addr) = start_addr.checked_add(size) else {
+			return;
+		};
+		if !self.last_marker.is_null() && start_addr == self.last_end {
+			self.join(end_addr);
+			return;
+		}
+		// Blocks start one word before a granule boundary, so that payloads
+		// are granule aligned.
+		let Some(first) = start_addr
+			.checked_add(WORD)
+			.and_then(|addr| addr.checked_next_multiple_of(GRANULE))
+			.map(|addr| addr - WORD)
+		else {
+			return;
+		};
+		// Leave room for the end marker
+		let Some(limit) = end_addr.checked_sub(WORD) else {
+			return;
+		};
+		if limit < first || limit - first < MIN_BLOCK {
+			return;
+		}
+		let block_size = (limit - first) & !(GRANULE - 1);
+		let block = start.add(first - start_addr);
+		set_header(block.add(block_size), USED);
+		self.insert(block, block_size, PREV_USED);
+		self.last_marker = block.add(block_size);
+		self.last_end = end_addr;
+	}
+
+	/// Grow the last region up to `end_addr`, turning its end marker into a
+	/// block and freeing it (which merges it with any free block before it).
+	unsafe fn join(&mut self, end_addr: usize) {
+		let marker = self.last_marker;
+		let Some(limit) = end_addr.checked_sub(WORD) else {
+			return;
+		};
+		let block_size = (limit - marker as usize) & !(GRANULE - 1);
+		if block_size < MIN_BLOCK {
+			return;
+		}
+		set_header(marker.add(block_size), USED);
+		set_header(marker, block_size | USED | (header(marker) & PREV_USED));
+		self.last_marker = marker.add(block_size);
+		self.last_end = end_addr;
+		self.dealloc(marker.add(WORD));
+	}
+
+	/// Get the number of bytes in blocks which are currently free.
+	pub fn free_bytes(&self) -> usize {
+		self.free_bytes
+	}
+
+	/// Allocate a block of memory.
+	///
+	/// Returns NULL if there isn't a large enough free block.
+	///
+	/// # Safety
+	///
+	/// The heap must have been initialised with valid memory.
+	pub unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+		if layout.align() <= GRANULE {
+			return self.alloc_block(layout.size());
+		}
+		// Over-allocate, and record where the block's payload really is in the
+		// word before the pointer we return. Headers always have `USED` set,
+		// and this offset never does.
+		let Some(padded) = layout.size().checked_add(layout.align()) else {
+			return null_mut();
+		};
+		let payload = self.alloc_block(padded);
+		if payload.is_null() {
+			return payload;
+		}
+		let offset = payload.align_offset(layout.align());
+		let aligned = payload.add(offset);
+		if offset != 0 {
+			set_header(aligned.sub(WORD), offset);
+		}
+		aligned
+	}
+
+	/// Return a block of memory to the heap.
+	///
+	/// # Safety
+	///
+	/// `ptr` must have been returned by this heap, and not already freed.
+	pub unsafe fn dealloc(&mut self, ptr: *mut u8) {
+		let mut block = block_of(ptr);
+		let mut size = block_size(block);
+		let mut flags = header(block) & PREV_USED;
+
+		// Merge with the next block if it is free
+		let next = block.add(size);
+		if header(next) & USED == 0 {
+			let next_size = block_size(next);
+			self.remove(next, next_size);
+			size += next_size;
+		}
+
+		// Merge with the previous block if it is free
+		if flags & PREV_USED == 0 {
+			let prev_size = *(block.sub(WORD) as *const usize);
+			block = block.sub(prev_size);
+			self.remove(block, prev_size);
+			size += prev_size;
+			flags = header(block) & PREV_USED;
+		}
+
+		self.insert(block, size, flags);
+	}
+
+	/// Change the size of a block of memory, moving it if necessary.
+	///
+	/// Returns NULL, leaving the old block untouched, if there isn't enough
+	/// memory.
+	///
+	/// # Safety
+	///
+	/// `ptr` must have been returned by this heap with the given layout, and
+	/// not already freed.
+	pub unsafe fn realloc(&mut self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+		if layout.align() <= GRANULE {
+			if let Some(needed) = block_size_for(new_size) {
+				let block = block_of(ptr);
+				let size = block_size(block);
+				let next = block.add(size);
+				let available = if header(next) & USED == 0 {
+					size + block_size(next)
+				} else {
+					size
+				};
+				if available >= needed {
+					if available > size {
+						self.remove(next, available - size);
+					}
+					self.claim(block, available, needed);
+					return ptr;
+				}
+			}
+		}
+		let Ok(new_layout) = Layout::from_size_align(new_size, layout.align()) else {
+			return null_mut();
+		};
+		let new_ptr = self.alloc(new_layout);
+		if !new_ptr.is_null() {
+			core::ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+			self.dealloc(ptr);
+		}
+		new_ptr
+	}
+
+	/// Get the number of bytes that can be stored at `ptr`.
+	///
+	/// # Safety
+	///
+	/// `ptr` must have been returned by this heap, and not already freed.
+	pub unsafe fn usable_size(&self, ptr: *mut u8) -> usize {
+		let block = block_of(ptr);
+		block.add(block_size(block)) as usize - ptr as usize
+	}
+
+	/// Allocate a block with a payload of at least `size` bytes.
+	unsafe fn alloc_block(&mut self, size: usize) -> *mut u8 {
+		let Some(needed) = block_size_for(size) else {
+			return null_mut();
+		};
+		let bin = bin_for(needed);
+
+		// First fit in the matching bin...
+		let mut candidate = self.bins[bin];
+		while !candidate.is_null() && block_size(candidate.cast()) < needed {
+			candidate = (*candidate).next;
+		}
+		// ...otherwise any block from a larger bin will do.
+		if candidate.is_null() {
+			let larger = self.bitmap & (usize::MAX << bin).wrapping_shl(1);
+			if larger == 0 {
+				return null_mut();
+			}
+			candidate = self.bins[larger.trailing_zeros() as usize];
+		}
+
+		let block = candidate as *mut u8;
+		let size = block_size(block);
+		self.remove(block, size);
+		self.claim(block, size, needed);
+		block.add(WORD)
+	}
+
+	/// Mark the first `needed` bytes of the `size` byte block (which is not
+	/// on any free list) as used, and free the rest if it's big enough.
+	unsafe fn claim(&mut self, block: *mut u8, size: usize, needed: usize) {
+		let flags = header(block) & PREV_USED;
+		if size - needed >= MIN_BLOCK {
+			set_header(block, needed | USED | flags);
+			self.insert(block.add(needed), size - needed, PREV_USED);
+		} else {
+			set_header(block, size | USED | flags);
+			let next = block.add(size);
+			set_header(next, header(next) | PREV_USED);
+		}
+	}
+
+	/// Put a free block on the appropriate free list, and write its header
+	/// and footer.
+	unsafe fn insert(&mut self, block: *mut u8, size: usize, flags: usize) {
+		set_header(block, size | flags);
+		set_header(block.add(size - WORD), size);
+		let next = block.add(size);
+		set_header(next, header(next) & !PREV_USED);
+
+		let bin = bin_for(size);
+		let free = block as *mut FreeBlock;
+		(*free).prev = null_mut();
+		(*free).next = self.bins[bin];
+		if !self.bins[bin].is_null() {
+			(*self.bins[bin]).prev = free;
+		}
+		self.bins[bin] = free;
+		self.bitmap |= 1 << bin;
+		self.free_bytes += size;
+	}
+
+	/// Take a free block off its free list.
+	unsafe fn remove(&mut self, block: *mut u8, size: usize) {
+		let bin = bin_for(size);
+		let free = block as *mut FreeBlock;
+		if (*free).prev.is_null() {
+			self.bins[bin] = (*free).next;
+			if self.bins[bin].is_null() {
+				self.bitmap &= !(1 << bin);
+			}
+		} else {
+			(*(*free).prev).next = (*free).next;
+		}
+		if !(*free).next.is_null() {
+			(*(*free).next).prev = (*free).prev;
+		}
+		self.free_bytes -= size;
+	}
+}
+
+/// Read the header of a block.
+unsafe fn header(block: *mut u8) -> usize {
+	*(block as *const usize)
+}
+
+/// Write the header of a block.
+unsafe fn set_header(block: *mut u8, value: usize) {
+	*(block as *mut usize) = value;
+}
+
+/// Get the size of a block, in bytes, including its header.
+unsafe fn block_size(block: *mut u8) -> usize {
+	header(block) & !FLAGS
+}
+
+/// Find the block that a pointer returned by [`Heap::alloc`] belongs to.
+unsafe fn block_of(ptr: *mut u8) -> *mut u8 {
+	let word = header(ptr.sub(WORD));
+	if word & USED == 0 {
+		// This is an over-aligned allocation; the word holds the offset
+		ptr.sub(word).sub(WORD)
+	} else {
+		ptr.sub(WORD)
+	}
+}
+
+/// Get the size of the block we need to hold `size` bytes of payload.
+fn block_size_for(size: usize) -> Option<usize> {
+	let size = size.checked_add(WORD)?.checked_next_multiple_of(GRANULE)?;
+	Some(size.max(MIN_BLOCK))
+}
+
+/// Get the free list for blocks of the given size.
+fn bin_for(size: usize) -> usize {
+	size.ilog2() as usize
+}
+
+/// A heap protected by a spin lock.
+///
+/// This is not safe to use from interrupt handlers (see the module
+/// documentation).
+struct LockedHeap {
+	initialised: AtomicBool,
+	heap: SpinLock<Heap>,
+}
+
+impl LockedHeap {
+	/// Run `f` with exclusive access to the heap.
+	///
+	/// If the heap hasn't been given any memory yet, it is first given the
+	/// default region (if there is one).
+	fn with<R>(&self, f: impl FnOnce(&mut Heap) -> R) -> R {
+		self.heap.with(|heap| {
+			if !self.initialised.load(Ordering::Relaxed) {
+				if let Some((start, size)) = default_region() {
+					unsafe { heap.init(start, size) };
+				}
+				self.initialised.store(true, Ordering::Relaxed);
+			}
+			f(heap)
+		})
+	}
+}
+
+/// The heap used by `malloc` and by [`TinyHeap`].
+static HEAP: LockedHeap = LockedHeap {
+	initialised: AtomicBool::new(false),
+	heap: SpinLock::new(Heap::empty()),
+};
+
+/// The memory the heap uses if [`heap_init`] is not called before the first
+/// allocation.
+#[cfg(all(feature = "heap-linker-symbols", not(feature = "sbrk"), not(test)))]
+fn default_region() -> Option<(*mut u8, usize)> {
+	extern "C" {
+		static mut __heap_start: u8;
+		static mut __heap_end: u8;
+	}
+	// Older compilers need `unsafe` to take the address of an extern static
+	#[allow(unused_unsafe)]
+	let (start, end) = unsafe {
+		(
+			core::ptr::addr_of_mut!(__heap_start),
+			core::ptr::addr_of_mut!(__heap_end),
+		)
+	};
+	Some((start, (end as usize).saturating_sub(start as usize)))
+}
+
+/// The memory the heap uses if [`heap_init`] is not called before the first
+/// allocation.
+#[cfg(test)]
+fn default_region() -> Option<(*mut u8, usize)> {
+	static mut TEST_HEAP: [u8; 1 << 20] = [0; 1 << 20];
+	// Older compilers need `unsafe` to take the address of a static mut
+	#[allow(unused_unsafe)]
+	let start = unsafe { core::ptr::addr_of_mut!(TEST_HEAP) };
+	Some((start.cast(), 1 << 20))
+}
+
+/// The memory the heap uses if [`heap_init`] is not called before the first
+/// allocation.
+///
+/// With the `sbrk` feature, the heap starts empty and takes memory from `sbrk`
+/// instead (which may itself use the linker symbols).
+#[cfg(not(any(all(feature = "heap-linker-symbols", not(feature = "sbrk")), test)))]
+fn default_region() -> Option<(*mut u8, usize)> {
+	None
+}
+
+/// Give the heap a region of memory to manage.
+///
+/// This must be called before the first allocation, unless the
+/// `heap-linker-symbols` feature is enabled, in which case the heap uses the
+/// memory between the `__heap_start` and `__heap_end` linker symbols, or the
+/// `sbrk` feature is enabled, in which case the heap takes memory from `sbrk`.
+///
+/// # Safety
+///
+/// The region must be valid for reads and writes, and must not be used for
+/// anything else. Any existing allocations are forgotten.
+pub unsafe fn heap_init(start: *mut u8, size: usize) {
+	HEAP.with(|heap| heap.init(start, size));
+}
+
+/// C interface to [`heap_init`].
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn tinyrlibc_heap_init(start: *mut core::ffi::c_void, size: usize) {
+	heap_init(start.cast(), size);
+}
+
+/// The least memory the heap takes from `sbrk` at once
+#[cfg(feature = "sbrk")]
+const SBRK_CHUNK: usize = 4096;
+
+/// Take enough memory from `sbrk` to allocate `layout`, returning `false` if
+/// there isn't enough.
+#[cfg(feature = "sbrk")]
+unsafe fn grow(heap: &mut Heap, layout: Layout) -> bool {
+	// Room for the block (and any alignment padding), plus the region's
+	// alignment padding and end marker
+	let Some(size) = layout
+		.size()
+		.checked_add(layout.align() + MIN_BLOCK + 2 * GRANULE)
+		.and_then(|size| size.max(SBRK_CHUNK).checked_next_multiple_of(GRANULE))
+	else {
+		return false;
+	};
+	match crate::sbrk::grow(size) {
+		Some(start) => {
+			heap.extend(start, size);
+			true
+		}
+		None => false,
+	}
+}
+
+/// Allocate from the heap, as per [`GlobalAlloc::alloc`].
+///
+/// With the `sbrk` feature, the heap takes more memory from `sbrk` if it
+/// needs to.
+pub(crate) unsafe fn alloc(layout: Layout) -> *mut u8 {
+	HEAP.with(|heap| {
+		let ptr = heap.alloc(layout);
+		#[cfg(feature = "sbrk")]
+		if ptr.is_null() && grow(heap, layout) {
+			return heap.alloc(layout);
+		}
+		ptr
+	})
+}
+
+/// Allocate zeroed memory from the heap, as per [`GlobalAlloc::alloc_zeroed`].
+pub(crate) unsafe fn alloc_zeroed(layout: Layout) -> *mut u8 {
+	let ptr = alloc(layout);
+	if !ptr.is_null() {
+		core::ptr::write_bytes(ptr, 0, layout.size());
+	}
+	ptr
+}
+
+/// Resize an allocation, as per [`GlobalAlloc::realloc`].
+pub(crate) unsafe fn realloc(ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+	HEAP.with(|heap| {
+		let new_ptr = heap.realloc(ptr, layout, new_size);
+		#[cfg(feature = "sbrk")]
+		if new_ptr.is_null() {
+			let Ok(new_layout) = Layout::from_size_align(new_size, layout.align()) else {
+				return new_ptr;
+			};
+			if grow(heap, new_layout) {
+				return heap.realloc(ptr, layout, new_size);
+			}
+		}
+		new_ptr
+	})
+}
+
+/// Free an allocation, as per [`GlobalAlloc::dealloc`].
+pub(crate) unsafe fn dealloc(ptr: *mut u8, _layout: Layout) {
+	HEAP.with(|heap| heap.dealloc(ptr))
+}
+
+/// A Rust global allocator which uses the same heap as `malloc`.
+///
+/// ```rust,ignore
+/// #[global_allocator]
+/// static ALLOCATOR: tinyrlibc::TinyHeap = tinyrlibc::TinyHeap;
+/// ```
+pub struct TinyHeap;
+
+unsafe impl GlobalAlloc for TinyHeap {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		alloc(layout)
+	}
+
+	unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+		alloc_zeroed(layout)
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		dealloc(ptr, layout)
+	}
+
+	unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+		realloc(ptr, layout, new_size)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	/// Some suitably aligned memory to put a heap in
+	#[repr(align(64))]
+	struct Region([u8; 4096]);
+
+	fn layout(size: usize, align: usize) -> Layout {
+		Layout::from_size_align(size, align).unwrap()
+	}
+
+	#[test]
+	fn empty_heap() {
+		let mut heap = Heap::empty();
+		assert!(unsafe { heap.alloc(layout(1, 1)) }.is_null());
+		let mut tiny = [0u8; 8];
+		unsafe { heap.init(tiny.as_mut_ptr(), tiny.len()) };
+		assert!(unsafe { heap.alloc(layout(1, 1)) }.is_null());
+	}
+
+	#[test]
+	fn alloc_and_free() {
+		let mut region = Region([0; 4096]);
+		let mut heap = Heap::empty();
+		unsafe { heap.init(region.0.as_mut_ptr(), region.0.len()) };
+		let initial = heap.free_bytes();
+		assert!(initial > 4000);
+
+		let a = unsafe { heap.alloc(layout(100, 4)) };
+		let b = unsafe { heap.alloc(layout(200, 8)) };
+		let c = unsafe { heap.alloc(layout(1, 1)) };
+		for ptr in [a, b, c] {
+			assert!(!ptr.is_null());
+			assert_eq!(ptr as usize % GRANULE, 0);
+		}
+		assert!(unsafe { heap.usable_size(a) } >= 100);
+		unsafe {
+			core::ptr::write_bytes(a, 0xAA, 100);
+			core::ptr::write_bytes(b, 0xBB, 200);
+			core::ptr::write_bytes(c, 0xCC, 1);
+		}
+		assert_eq!(unsafe { *a.add(99) }, 0xAA);
+		assert_eq!(unsafe { *b }, 0xBB);
+
+		// Free in an order that exercises merging both ways
+		unsafe {
+			heap.dealloc(a);
+			heap.dealloc(c);
+			heap.dealloc(b);
+		}
+		assert_eq!(heap.free_bytes(), initial);
+
+		// Everything merged back together, so one big block is available
+		let big = unsafe { heap.alloc(layout(initial - WORD, 1)) };
+		assert!(!big.is_null());
+		assert_eq!(heap.free_bytes(), 0);
+		unsafe { heap.dealloc(big) };
+		assert_eq!(heap.free_bytes(), initial);
+	}
+
+	#[test]
+	fn extend() {
+		let mut first = Region([0; 4096]);
+		let mut second = Region([0; 4096]);
+		let mut heap = Heap::empty();
+		unsafe { heap.init(first.0.as_mut_ptr(), first.0.len()) };
+		let initial = heap.free_bytes();
+		unsafe { heap.extend(second.0.as_mut_ptr(), second.0.len()) };
+		assert_eq!(heap.free_bytes(), 2 * initial);
+
+		// Each region can satisfy one large allocation
+		let a = unsafe { heap.alloc(layout(3000, 8)) };
+		let b = unsafe { heap.alloc(layout(3000, 8)) };
+		assert!(!a.is_null() && !b.is_null());
+		assert!(unsafe { heap.alloc(layout(3000, 8)) }.is_null());
+		unsafe {
+			heap.dealloc(a);
+			heap.dealloc(b);
+		}
+		assert_eq!(heap.free_bytes(), 2 * initial);
+	}
+
+	#[test]
+	fn extend_joins_adjacent() {
+		let mut region = Region([0; 4096]);
+		let (first, second) = region.0.split_at_mut(2048);
+		let mut heap = Heap::empty();
+		unsafe { heap.init(first.as_mut_ptr(), first.len()) };
+		let a = unsafe { heap.alloc(layout(1000, 8)) };
+		unsafe { heap.extend(second.as_mut_ptr(), second.len()) };
+		// The free space either side of the old end marker is one block
+		let b = unsafe { heap.alloc(layout(2500, 8)) };
+		assert!(!a.is_null() && !b.is_null());
+		unsafe {
+			heap.dealloc(a);
+			heap.dealloc(b);
+		}
+		// Which is the same as having had all the memory from the start
+		let joined = heap.free_bytes();
+		unsafe { heap.init(region.0.as_mut_ptr(), region.0.len()) };
+		assert_eq!(heap.free_bytes(), joined);
+		assert!(!unsafe { heap.alloc(layout(4000, 8)) }.is_null());
+	}
+
+	#[test]
+	#[cfg(feature = "sbrk")]
+	#[allow(unused_unsafe)]
+	fn grows_with_sbrk() {
+		let _guard = crate::test_lock();
+		// Memory given to the shared heap must outlive the test
+		static mut BREAK_REGION: [u8; 3 << 20] = [0; 3 << 20];
+		let start = unsafe { core::ptr::addr_of_mut!(BREAK_REGION) }.cast::<u8>();
+		unsafe { crate::sbrk::sbrk_init(start, 3 << 20) };
+
+		// More than the default test heap holds
+		let ptr = unsafe { alloc(layout(2 << 20, 64)) };
+		assert!(!ptr.is_null());
+		assert_eq!(ptr as usize % 64, 0);
+		let brk = crate::sbrk::sbrk(0) as usize;
+		assert!((start as usize..brk).contains(&(ptr as usize)));
+		assert!(brk - start as usize >= 2 << 20);
+
+		// Whatever sbrk hands out next is beyond the heap's memory
+		let next = crate::sbrk::sbrk(16) as usize;
+		assert_eq!(next, brk);
+		unsafe { dealloc(ptr, layout(2 << 20, 64)) };
+	}
+
+	#[test]
+	fn exhaustion() {
+		let mut region = Region([0; 4096]);
+		let mut heap = Heap::empty();
+		unsafe { heap.init(region.0.as_mut_ptr(), region.0.len()) };
+		let mut ptrs = Vec::new();
+		loop {
+			let ptr = unsafe { heap.alloc(layout(64, 8)) };
+			if ptr.is_null() {
+				break;
+			}
+			ptrs.push(ptr);
+		}
+		assert!(ptrs.len() > 40);
+		assert!(unsafe { heap.alloc(layout(isize::MAX as usize, 1)) }.is_null());
+		for ptr in ptrs {
+			unsafe { heap.dealloc(ptr) };
+		}
+		assert!(!unsafe { heap.alloc(layout(3000, 8)) }.is_null());
+	}
+
+	#[test]
+	fn over_aligned() {
+		let mut region = Region([0; 4096]);
+		let mut heap = Heap::empty();
+		unsafe { heap.init(region.0.as_mut_ptr(), region.0.len()) };
+		let initial = heap.free_bytes();
+		let small = unsafe { heap.alloc(layout(8, 8)) };
+		let ptrs: Vec<_> = [64, 128, 256]
+			.iter()
+			.map(|&align| {
+				let ptr = unsafe { heap.alloc(layout(40, align)) };
+				assert!(!ptr.is_null());
+				assert_eq!(ptr as usize % align, 0);
+				assert!(unsafe { heap.usable_size(ptr) } >= 40);
+				ptr
+			})
+			.collect();
+		for ptr in ptrs {
+			unsafe { heap.dealloc(ptr) };
+		}
+		unsafe { heap.dealloc(small) };
+		assert_eq!(heap.free_bytes(), initial);
+	}
+
+	#[test]
+	fn realloc_in_place() {
+		let mut region = Region([0; 4096]);
+		let mut heap = Heap::empty();
+		unsafe { heap.init(region.0.as_mut_ptr(), region.0.len()) };
+		let initial = heap.free_bytes();
+		let a = unsafe { heap.alloc(layout(16, 8)) };
+		unsafe { core::ptr::write_bytes(a, 0x5A, 16) };
+		// Grows into the free space that follows it
+		let b = unsafe { heap.realloc(a, layout(16, 8), 1000) };
+		assert_eq!(a, b);
+		// Shrinks, giving the space back
+		let c = unsafe { heap.realloc(b, layout(1000, 8), 8) };
+		assert_eq!(a, c);
+		assert_eq!(unsafe { *c.add(7) }, 0x5A);
+		unsafe { heap.dealloc(c) };
+		assert_eq!(heap.free_bytes(), initial);
+	}
+
+	#[test]
+	fn realloc_moves() {
+		let mut region = Region([0; 4096]);
+		let mut heap = Heap::empty();
+		unsafe { heap.init(region.0.as_mut_ptr(), region.0.len()) };
+		let a = unsafe { heap.alloc(layout(16, 8)) };
+		let blocker = unsafe { heap.alloc(layout(16, 8)) };
+		unsafe { core::ptr::write_bytes(a, 0x5A, 16) };
+		let b = unsafe { heap.realloc(a, layout(16, 8), 100) };
+		assert_ne!(a, b);
+		assert_eq!(unsafe { *b.add(15) }, 0x5A);
+		assert!(unsafe { heap.realloc(b, layout(100, 8), 10000) }.is_null());
+		unsafe {
+			heap.dealloc(b);
+			heap.dealloc(blocker);
+		}
+	}
+
+	#[test]
+	fn random_churn() {
+		let mut region = Region([0; 4096]);
+		let mut heap = Heap::empty();
+		unsafe { heap.init(region.0.as_mut_ptr(), region.0.len()) };
+		let initial = heap.free_bytes();
+		let mut seed = 1u32;
+		let mut live: Vec<(*mut u8, usize, u8)> = Vec::new();
+		for round in 0..2000 {
+			seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+			let choice = (seed >> 16) as usize;
+			if choice % 3 == 0 && !live.is_empty() {
+				let (ptr, size, fill) = live.swap_remove(choice % live.len());
+				for i in 0..size {
+					assert_eq!(unsafe { *ptr.add(i) }, fill);
+				}
+				unsafe { heap.dealloc(ptr) };
+			} else {
+				let size = 1 + choice % 200;
+				let align = 1 << (choice % 7);
+				let ptr = unsafe { heap.alloc(layout(size, align)) };
+				if !ptr.is_null() {
+					assert_eq!(ptr as usize % align, 0);
+					unsafe { core::ptr::write_bytes(ptr, round as u8, size) };
+					live.push((ptr, size, round as u8));
+				}
+			}
+		}
+		for (ptr, _, _) in live {
+			unsafe { heap.dealloc(ptr) };
+		}
+		assert_eq!(heap.free_bytes(), initial);
+	}
+
+	#[test]
+	fn global_heap() {
+		let ptr = unsafe { TinyHeap.alloc_zeroed(layout(32, 32)) };
+		assert!(!ptr.is_null());
+		assert_eq!(ptr as usize % 32, 0);
+		assert_eq!(unsafe { *ptr.add(31) }, 0);
+		unsafe { TinyHeap.dealloc(ptr, layout(32, 32)) };
+	}
+}
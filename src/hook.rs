@@ -0,0 +1,21 @@
+//! Slots for the hooks and handlers the application can set, such as the
+//! abort hook and the heap violation handler.
+//!
+//! Licensed under the Blue Oak Model Licence 1.0.0
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Put `new` in a hook (or handler) slot, returning the old value.
+///
+/// Without atomic read-modify-write instructions (as on thumbv6m), this has
+/// to be a load then a store.
+pub(crate) fn swap(slot: &AtomicUsize, new: usize) -> usize {
+	#[cfg(target_has_atomic = "ptr")]
+	return slot.swap(new, Ordering::AcqRel);
+	#[cfg(not(target_has_atomic = "ptr"))]
+	{
+		let old = slot.load(Ordering::Acquire);
+		slot.store(new, Ordering::Release);
+		old
+	}
+}
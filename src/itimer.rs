@@ -0,0 +1,349 @@
+//! Rust implementation of the C library functions `alarm`, `setitimer` and
+//! `getitimer`.
+//!
+//! Only the real-time timer (`ITIMER_REAL`) is supported. Time is counted
+//! in milliseconds by [`tinyrlibc_timer_tick`], which the application calls
+//! from its timer interrupt. When the timer expires, `SIGALRM` is posted (as
+//! with `tinyrlibc_signal_post`), and its handler runs on the next call to
+//! `tinyrlibc_signal_dispatch`.
+//!
+//! Times are kept in 32-bit milliseconds, so the longest is about 49 days;
+//! longer times are cut short to that.
+//!
+//! Licensed under the Blue Oak Model Licence 1.0.0
+
+use core::ffi::{c_int, c_long, c_uint};
+
+use portable_atomic::{AtomicU32, Ordering};
+
+use crate::errno::{set_errno, EINVAL};
+use crate::signal::{tinyrlibc_signal_post, SIGALRM};
+
+/// Seconds, as used in [`timeval`]
+#[allow(non_camel_case_types)]
+pub type time_t = i64;
+
+/// Microseconds, as used in [`timeval`]
+#[allow(non_camel_case_types)]
+pub type suseconds_t = c_long;
+
+/// A time, in seconds and microseconds.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[allow(non_camel_case_types)]
+pub struct timeval {
+	/// Seconds
+	pub tv_sec: time_t,
+	/// Microseconds, from 0 to 999,999
+	pub tv_usec: suseconds_t,
+}
+
+/// The setting of an interval timer.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[allow(non_camel_case_types)]
+pub struct itimerval {
+	/// The period to reload the timer with when it expires (zero to stop)
+	pub it_interval: timeval,
+	/// The time until the timer next expires (zero if it is stopped)
+	pub it_value: timeval,
+}
+
+/// The real-time timer, which sends `SIGALRM`
+pub const ITIMER_REAL: c_int = 0;
+
+/// The virtual timer (not supported)
+pub const ITIMER_VIRTUAL: c_int = 1;
+
+/// The profiling timer (not supported)
+pub const ITIMER_PROF: c_int = 2;
+
+/// The real-time timer, in milliseconds.
+///
+/// Both parts are 32-bit, so they can be changed without a lock on any
+/// target with atomic instructions: [`tinyrlibc_timer_tick`] runs in an
+/// interrupt handler, so it can't wait for a lock held by the code it
+/// interrupted.
+struct Timer {
+	/// The time until it expires, or 0 if it is stopped
+	remaining: AtomicU32,
+	/// The period to reload it with when it expires
+	interval: AtomicU32,
+}
+
+impl Timer {
+	const fn new() -> Timer {
+		Timer {
+			remaining: AtomicU32::new(0),
+			interval: AtomicU32::new(0),
+		}
+	}
+
+	/// Set the timer, returning the old `(remaining, interval)`.
+	///
+	/// The timer is stopped while the interval changes, so a tick part way
+	/// through can't mix the old and new settings.
+	fn set(&self, remaining: u32, interval: u32) -> (u32, u32) {
+		let old_remaining = self.remaining.swap(0, Ordering::AcqRel);
+		let old_interval = self.interval.swap(interval, Ordering::AcqRel);
+		self.remaining.store(remaining, Ordering::Release);
+		(old_remaining, old_interval)
+	}
+
+	/// Get `(remaining, interval)`.
+	fn get(&self) -> (u32, u32) {
+		(
+			self.remaining.load(Ordering::Acquire),
+			self.interval.load(Ordering::Acquire),
+		)
+	}
+
+	/// Count down `elapsed` milliseconds, returning `true` if the timer
+	/// expired.
+	///
+	/// When an interval timer expires, any time past the expiry counts
+	/// towards the next period, so the timer doesn't drift.
+	fn tick(&self, elapsed: u32) -> bool {
+		self.remaining
+			.fetch_update(
+				Ordering::AcqRel,
+				Ordering::Acquire,
+				|remaining| match remaining {
+					0 => None,
+					_ if elapsed < remaining => Some(remaining - elapsed),
+					_ => match self.interval.load(Ordering::Acquire) {
+						0 => Some(0),
+						interval => Some(interval - (elapsed - remaining) % interval),
+					},
+				},
+			)
+			.is_ok_and(|remaining| elapsed >= remaining)
+	}
+}
+
+/// The timer
+static TIMER: Timer = Timer::new();
+
+/// Stop the timer, between tests.
+#[cfg(test)]
+pub(crate) fn reset() {
+	TIMER.set(0, 0);
+}
+
+/// Convert a `timeval` to milliseconds, rounding up, or `None` if it is
+/// invalid. Times too long for the timer are cut short.
+fn to_ms(time: &timeval) -> Option<u32> {
+	let sec = u64::try_from(time.tv_sec).ok()?;
+	let usec = u64::try_from(time.tv_usec)
+		.ok()
+		.filter(|&usec| usec < 1_000_000)?;
+	let ms = sec.saturating_mul(1000).saturating_add(usec.div_ceil(1000));
+	Some(u32::try_from(ms).unwrap_or(u32::MAX))
+}
+
+/// Convert milliseconds to a `timeval`.
+fn to_timeval(ms: u32) -> timeval {
+	timeval {
+		tv_sec: time_t::from(ms / 1000),
+		tv_usec: ((ms % 1000) * 1000) as suseconds_t,
+	}
+}
+
+/// Count down the timer, posting `SIGALRM` if it expires.
+///
+/// Call this from a timer interrupt (or anywhere else), with the number of
+/// milliseconds since the last call.
+#[cfg_attr(all(not(test), feature = "signal"), no_mangle)]
+pub extern "C" fn tinyrlibc_timer_tick(elapsed_ms: u32) {
+	if TIMER.tick(elapsed_ms) {
+		tinyrlibc_signal_post(SIGALRM);
+	}
+}
+
+/// Rust implementation of the POSIX `alarm` function.
+///
+/// Sends `SIGALRM` in `seconds` seconds (or never, if `seconds` is 0),
+/// replacing any timer set before. Returns the number of seconds the old
+/// timer had left (rounded up), or 0 if there wasn't one.
+#[cfg_attr(all(not(test), feature = "signal"), no_mangle)]
+pub extern "C" fn alarm(seconds: c_uint) -> c_uint {
+	let (remaining, _) = TIMER.set(seconds.saturating_mul(1000), 0);
+	remaining.div_ceil(1000)
+}
+
+/// Rust implementation of the POSIX `getitimer` function.
+///
+/// Stores the timer's setting in `value`. Returns 0, or -1 with `errno` set to
+/// `EINVAL` if `which` isn't `ITIMER_REAL`.
+#[cfg_attr(all(not(test), feature = "signal"), no_mangle)]
+pub unsafe extern "C" fn getitimer(which: c_int, value: *mut itimerval) -> c_int {
+	if which != ITIMER_REAL {
+		set_errno(EINVAL);
+		return -1;
+	}
+	let (remaining, interval) = TIMER.get();
+	*value = itimerval {
+		it_interval: to_timeval(interval),
+		it_value: to_timeval(remaining),
+	};
+	0
+}
+
+/// Rust implementation of the POSIX `setitimer` function.
+///
+/// Sets the timer from `value`, storing the old setting in `ovalue` if it is
+/// not NULL. Times are rounded up to whole milliseconds. Returns 0, or -1
+/// with `errno` set to `EINVAL` if `which` isn't `ITIMER_REAL` or `value` is
+/// invalid.
+#[cfg_attr(all(not(test), feature = "signal"), no_mangle)]
+pub unsafe extern "C" fn setitimer(
+	which: c_int,
+	value: *const itimerval,
+	ovalue: *mut itimerval,
+) -> c_int {
+	let value = &*value;
+	let (Some(remaining), Some(interval), ITIMER_REAL) =
+		(to_ms(&value.it_value), to_ms(&value.it_interval), which)
+	else {
+		set_errno(EINVAL);
+		return -1;
+	};
+	let (old_remaining, old_interval) = TIMER.set(remaining, interval);
+	if let Some(ovalue) = ovalue.as_mut() {
+		*ovalue = itimerval {
+			it_interval: to_timeval(old_interval),
+			it_value: to_timeval(old_remaining),
+		};
+	}
+	0
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::errno::errno;
+
+	fn time(tv_sec: time_t, tv_usec: suseconds_t) -> timeval {
+		timeval { tv_sec, tv_usec }
+	}
+
+	#[test]
+	fn alarm_counts_down() {
+		let _guard = crate::test_lock();
+		assert_eq!(alarm(2), 0);
+		assert!(!TIMER.tick(1500));
+		assert_eq!(alarm(5), 1);
+		assert!(!TIMER.tick(4999));
+		assert!(TIMER.tick(1));
+		// It doesn't repeat
+		assert!(!TIMER.tick(10_000));
+		assert_eq!(alarm(0), 0);
+	}
+
+	#[test]
+	fn interval_timer() {
+		let _guard = crate::test_lock();
+		let value = itimerval {
+			it_interval: time(0, 250_000),
+			it_value: time(1, 1),
+		};
+		let mut old = itimerval::default();
+		assert_eq!(unsafe { setitimer(ITIMER_REAL, &value, &mut old) }, 0);
+		assert_eq!(old, itimerval::default());
+
+		// The first expiry is rounded up to a whole millisecond
+		assert!(!TIMER.tick(1000));
+		assert!(TIMER.tick(1));
+		for _ in 0..3 {
+			assert!(!TIMER.tick(249));
+			assert!(TIMER.tick(1));
+		}
+		assert!(!TIMER.tick(100));
+
+		let mut current = itimerval::default();
+		assert_eq!(unsafe { getitimer(ITIMER_REAL, &mut current) }, 0);
+		assert_eq!(current.it_interval, time(0, 250_000));
+		assert_eq!(current.it_value, time(0, 150_000));
+		assert_eq!(alarm(0), 1);
+	}
+
+	#[test]
+	fn interval_timer_does_not_drift() {
+		let _guard = crate::test_lock();
+		let value = itimerval {
+			it_interval: time(0, 100_000),
+			it_value: time(0, 100_000),
+		};
+		assert_eq!(
+			unsafe { setitimer(ITIMER_REAL, &value, core::ptr::null_mut()) },
+			0
+		);
+		// A late tick counts towards the next period
+		assert!(TIMER.tick(130));
+		assert_eq!(TIMER.get(), (70, 100));
+		assert!(!TIMER.tick(69));
+		assert!(TIMER.tick(1));
+		// Even if several periods went by
+		assert!(TIMER.tick(350));
+		assert_eq!(TIMER.get(), (50, 100));
+		assert_eq!(alarm(0), 1);
+	}
+
+	#[test]
+	fn sends_sigalrm() {
+		use crate::signal::{signal, tinyrlibc_signal_dispatch};
+		use portable_atomic::AtomicUsize;
+		let _guard = crate::test_lock();
+		static ALARMS: AtomicUsize = AtomicUsize::new(0);
+		extern "C" fn handler(sig: c_int) {
+			ALARMS.fetch_add(sig as usize, Ordering::Relaxed);
+		}
+		unsafe { signal(SIGALRM, handler as *const fn(c_int) as usize) };
+		assert_eq!(alarm(1), 0);
+		tinyrlibc_timer_tick(999);
+		assert_eq!(tinyrlibc_signal_dispatch(), 0);
+		tinyrlibc_timer_tick(1);
+		// The handler only runs from the dispatcher
+		assert_eq!(ALARMS.load(Ordering::Relaxed), 0);
+		assert_eq!(tinyrlibc_signal_dispatch(), 1);
+		assert_eq!(ALARMS.load(Ordering::Relaxed), SIGALRM as usize);
+		tinyrlibc_timer_tick(10_000);
+		assert_eq!(tinyrlibc_signal_dispatch(), 0);
+	}
+
+	#[test]
+	fn clamps_long_times() {
+		let _guard = crate::test_lock();
+		let value = itimerval {
+			it_interval: time(0, 0),
+			it_value: time(time_t::MAX, 0),
+		};
+		assert_eq!(
+			unsafe { setitimer(ITIMER_REAL, &value, core::ptr::null_mut()) },
+			0
+		);
+		assert_eq!(TIMER.get(), (u32::MAX, 0));
+		assert_eq!(alarm(c_uint::MAX), u32::MAX.div_ceil(1000));
+		assert_eq!(TIMER.get(), (u32::MAX, 0));
+	}
+
+	#[test]
+	fn invalid() {
+		let _guard = crate::test_lock();
+		let mut value = itimerval::default();
+		set_errno(0);
+		assert_eq!(unsafe { getitimer(ITIMER_VIRTUAL, &mut value) }, -1);
+		assert_eq!(errno(), EINVAL);
+		for bad in [time(0, 1_000_000), time(-1, 0), time(0, -1)] {
+			value.it_value = bad;
+			set_errno(0);
+			let result = unsafe { setitimer(ITIMER_REAL, &value, core::ptr::null_mut()) };
+			assert_eq!(result, -1);
+			assert_eq!(errno(), EINVAL);
+		}
+		value.it_value = time(1, 0);
+		let result = unsafe { setitimer(ITIMER_PROF, &value, core::ptr::null_mut()) };
+		assert_eq!(result, -1);
+		assert_eq!(TIMER.get(), (0, 0));
+	}
+}
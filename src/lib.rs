@@ -13,11 +13,28 @@
 
 // Stateless implementations.
 // rustfmt will keep these in alphabetical order.
+mod abort;
 mod abs;
+mod errno;
 mod itoa;
+mod locale;
 mod memchr;
+mod multibyte;
 mod qsort;
 mod rand_r;
+#[cfg(all(
+	feature = "setjmp",
+	any(
+		target_arch = "arm",
+		target_arch = "riscv32",
+		target_arch = "riscv64",
+		all(
+			target_arch = "x86_64",
+			not(any(target_os = "windows", target_vendor = "apple"))
+		)
+	)
+))]
+mod setjmp;
 mod snprintf;
 mod strcat;
 mod strchr;
@@ -32,36 +49,196 @@ mod strrchr;
 mod strspn;
 mod strstr;
 mod strtol;
+mod wchar;
 
 // Stateful implementations (which hence are optional).
 // rustfmt will keep these in alphabetical order.
+#[cfg(feature = "c-malloc-allocator")]
+mod c_malloc;
+#[cfg(feature = "cxxabi")]
+mod cxxabi;
+#[cfg(feature = "exit")]
+mod exit;
+#[cfg(feature = "heap")]
+mod heap;
+#[cfg(feature = "signal")]
+mod itimer;
 #[cfg(feature = "alloc")]
 mod malloc;
+#[cfg(feature = "malloc-debug")]
+mod malloc_debug;
+#[cfg(feature = "malloc-leaks")]
+mod malloc_leaks;
+#[cfg(feature = "alloc")]
+mod malloc_oom;
+#[cfg(feature = "malloc-pools")]
+mod malloc_pools;
 #[cfg(feature = "rand")]
 mod rand;
+#[cfg(feature = "sbrk")]
+mod sbrk;
 #[cfg(feature = "signal")]
 mod signal;
 
+// Crate-internal helpers.
+mod hook;
+#[cfg(any(
+	feature = "exit",
+	feature = "heap",
+	feature = "malloc-leaks",
+	feature = "malloc-pools",
+	feature = "sbrk"
+))]
+mod spinlock;
+
+// Rust-only interfaces.
+pub mod sort;
+
 // Public re-exports.
 // rustfmt will keep these in alphabetical order.
+pub use self::abort::{abort, tinyrlibc_set_abort_hook, AbortHook};
 #[cfg(feature = "abs")]
 pub use self::abs::abs;
+#[cfg(feature = "c-malloc-allocator")]
+pub use self::c_malloc::CMallocAllocator;
+#[cfg(feature = "cxxabi")]
+pub use self::cxxabi::{
+	__aeabi_atexit, __cxa_atexit, __cxa_finalize, __cxa_guard_abort, __cxa_guard_acquire,
+	__cxa_guard_release, __cxa_pure_virtual, __dso_handle, __guard, operator_delete,
+	operator_delete_array, operator_delete_array_nothrow, operator_delete_array_sized,
+	operator_delete_nothrow, operator_delete_sized, operator_new, operator_new_array,
+	operator_new_array_nothrow, operator_new_nothrow, CxaDestructor, DsoHandle,
+};
+#[cfg(feature = "errno")]
+pub use self::errno::{__errno, __errno_location};
+pub use self::errno::{errno, set_errno, EILSEQ, EINVAL, ENOMEM, ERANGE};
+#[cfg(feature = "exit")]
+pub use self::exit::{
+	_Exit, at_quick_exit, atexit, exit, quick_exit, tinyrlibc_set_exit_hook,
+	tinyrlibc_set_flush_hook, ExitHandler, ExitHook, FlushHook, ATEXIT_MAX, EXIT_FAILURE,
+	EXIT_SUCCESS,
+};
+#[cfg(feature = "heap")]
+pub use self::heap::{heap_init, tinyrlibc_heap_init, Heap, TinyHeap};
+#[cfg(feature = "signal")]
+pub use self::itimer::{
+	alarm, getitimer, itimerval, setitimer, suseconds_t, time_t, timeval, tinyrlibc_timer_tick,
+	ITIMER_PROF, ITIMER_REAL, ITIMER_VIRTUAL,
+};
 #[cfg(feature = "itoa")]
 pub use self::itoa::itoa;
 #[cfg(feature = "utoa")]
 pub use self::itoa::utoa;
+#[cfg(feature = "localeconv")]
+pub use self::locale::localeconv;
+#[cfg(feature = "setlocale")]
+pub use self::locale::setlocale;
+#[cfg(feature = "strcoll")]
+pub use self::locale::strcoll;
+#[cfg(feature = "strxfrm")]
+pub use self::locale::strxfrm;
+pub use self::locale::{lconv, nl_item};
+#[cfg(feature = "nl_langinfo")]
+pub use self::locale::{
+	nl_langinfo, ABDAY_1, ABMON_1, ALT_DIGITS, AM_STR, CODESET, CRNCYSTR, DAY_1, D_FMT, D_T_FMT,
+	ERA, ERA_D_FMT, ERA_D_T_FMT, ERA_T_FMT, MON_1, NOEXPR, PM_STR, RADIXCHAR, THOUSEP, T_FMT,
+	T_FMT_AMPM, YESEXPR,
+};
 #[cfg(feature = "alloc")]
-pub use self::malloc::{calloc, free, malloc, realloc};
+pub use self::malloc::{
+	aligned_alloc, calloc, free, malloc, malloc_usable_size, memalign, posix_memalign, realloc,
+	reallocarray, valloc,
+};
+#[cfg(feature = "malloc-stats")]
+pub use self::malloc::{heap_stats, tinyrlibc_mallinfo, HeapStats};
+#[cfg(feature = "malloc-debug")]
+pub use self::malloc_debug::{
+	tinyrlibc_set_heap_violation_handler, HeapViolation, HeapViolationHandler, FREED_BYTE,
+	FRESH_BYTE, GUARD_BYTE,
+};
+#[cfg(feature = "malloc-leaks")]
+pub use self::malloc_leaks::{
+	assert_no_leaks_since, leak_checkpoint, leaks_since, tinyrlibc_leak_checkpoint,
+	tinyrlibc_leak_set_caller, tinyrlibc_leaks_since, LeakCheckpoint, LiveAllocation,
+	LEAK_TABLE_SIZE,
+};
+#[cfg(feature = "alloc")]
+pub use self::malloc_oom::{
+	set_oom_handler, tinyrlibc_set_oom_handler, OomHandler, RustOomHandler,
+};
+#[cfg(feature = "malloc-pools")]
+pub use self::malloc_pools::{tinyrlibc_pool_register, Pool, MAX_POOLS};
 #[cfg(feature = "memchr")]
 pub use self::memchr::memchr;
+#[cfg(feature = "mblen")]
+pub use self::multibyte::mblen;
+#[cfg(feature = "mbrlen")]
+pub use self::multibyte::mbrlen;
+#[cfg(feature = "mbrtowc")]
+pub use self::multibyte::mbrtowc;
+#[cfg(feature = "mbsinit")]
+pub use self::multibyte::mbsinit;
+#[cfg(feature = "mbsrtowcs")]
+pub use self::multibyte::mbsrtowcs;
+#[cfg(feature = "mbstowcs")]
+pub use self::multibyte::mbstowcs;
+#[cfg(feature = "mbtowc")]
+pub use self::multibyte::mbtowc;
+#[cfg(feature = "wcrtomb")]
+pub use self::multibyte::wcrtomb;
+#[cfg(feature = "wcsrtombs")]
+pub use self::multibyte::wcsrtombs;
+#[cfg(feature = "wcstombs")]
+pub use self::multibyte::wcstombs;
+#[cfg(feature = "wctomb")]
+pub use self::multibyte::wctomb;
+pub use self::multibyte::{mbstate_t, MB_CUR_MAX, MB_LEN_MAX};
+#[cfg(feature = "bsearch")]
+pub use self::qsort::bsearch;
 #[cfg(feature = "qsort")]
 pub use self::qsort::qsort;
+#[cfg(feature = "qsort_r")]
+pub use self::qsort::{qsort_r, qsort_r_bsd};
 #[cfg(feature = "rand")]
 pub use self::rand::{rand, srand};
 #[cfg(feature = "rand_r")]
 pub use self::rand_r::{rand_r, RAND_MAX};
+#[cfg(feature = "sbrk")]
+pub use self::sbrk::{_sbrk, brk, sbrk, sbrk_init, tinyrlibc_sbrk_init};
+#[cfg(all(
+	feature = "setjmp",
+	any(
+		target_arch = "arm",
+		target_arch = "riscv32",
+		target_arch = "riscv64",
+		all(
+			target_arch = "x86_64",
+			not(any(target_os = "windows", target_vendor = "apple"))
+		)
+	)
+))]
+pub use self::setjmp::{jmp_buf, longjmp, sigjmp_buf, siglongjmp};
+#[cfg(feature = "signal")]
+pub use self::signal::{
+	raise, signal, SignalHandler, NSIG, SIGABRT, SIGALRM, SIGBUS, SIGCHLD, SIGCONT, SIGEMT, SIGFPE,
+	SIGHUP, SIGILL, SIGINT, SIGIO, SIGKILL, SIGLOST, SIGPIPE, SIGPROF, SIGPWR, SIGQUIT, SIGSEGV,
+	SIGSTOP, SIGSYS, SIGTERM, SIGTRAP, SIGTSTP, SIGTTIN, SIGTTOU, SIGURG, SIGUSR1, SIGUSR2,
+	SIGVTALRM, SIGWINCH, SIGXCPU, SIGXFSZ, SIG_DFL, SIG_ERR, SIG_IGN,
+};
 #[cfg(feature = "signal")]
-pub use self::signal::{abort, raise, signal};
+pub use self::signal::{
+	sigaction, sigaddset, sigdelset, sigemptyset, sigfillset, sigismember, sigpending, sigprocmask,
+	sigset_t, tinyrlibc_set_termination_hook, tinyrlibc_signal_dispatch, tinyrlibc_signal_post,
+	TerminationHook, SA_NODEFER, SA_RESETHAND, SIG_BLOCK, SIG_SETMASK, SIG_UNBLOCK,
+};
+#[cfg(feature = "signal")]
+pub use self::signal::{
+	siginfo_t, sigval, tinyrlibc_deliver_fault, SigInfoHandler, BUS_ADRALN, BUS_ADRERR, FPE_INTDIV,
+	FPE_INTOVF, ILL_ILLADR, ILL_ILLOPC, ILL_ILLOPN, SA_SIGINFO, SEGV_ACCERR, SEGV_MAPERR,
+	SI_KERNEL, SI_USER,
+};
+#[cfg(feature = "signal-rt")]
+pub use self::signal::{SIGRTMAX, SIGRTMIN};
 #[cfg(feature = "strcat")]
 pub use self::strcat::strcat;
 #[cfg(feature = "strchr")]
@@ -108,3 +285,68 @@ pub use self::strtol::strtoul;
 pub use self::strtol::strtoull;
 #[cfg(feature = "strtoumax")]
 pub use self::strtol::strtoumax;
+#[cfg(feature = "wcscat")]
+pub use self::wchar::wcscat;
+#[cfg(feature = "wcschr")]
+pub use self::wchar::wcschr;
+#[cfg(feature = "wcscmp")]
+pub use self::wchar::wcscmp;
+#[cfg(feature = "wcscpy")]
+pub use self::wchar::wcscpy;
+#[cfg(feature = "wcslen")]
+pub use self::wchar::wcslen;
+#[cfg(feature = "wcsncmp")]
+pub use self::wchar::wcsncmp;
+#[cfg(feature = "wcsncpy")]
+pub use self::wchar::wcsncpy;
+#[cfg(feature = "wcsrchr")]
+pub use self::wchar::wcsrchr;
+#[cfg(feature = "wcsstr")]
+pub use self::wchar::wcsstr;
+#[cfg(feature = "wcstol")]
+pub use self::wchar::wcstol;
+#[cfg(feature = "wcstoll")]
+pub use self::wchar::wcstoll;
+#[cfg(feature = "wcstoul")]
+pub use self::wchar::wcstoul;
+#[cfg(feature = "wcstoull")]
+pub use self::wchar::wcstoull;
+#[cfg(feature = "wmemchr")]
+pub use self::wchar::wmemchr;
+#[cfg(feature = "wmemcmp")]
+pub use self::wchar::wmemcmp;
+#[cfg(feature = "wmemcpy")]
+pub use self::wchar::wmemcpy;
+#[cfg(feature = "wmemmove")]
+pub use self::wchar::wmemmove;
+#[cfg(feature = "wmemset")]
+pub use self::wchar::wmemset;
+pub use self::wchar::{wchar_t, wint_t, WEOF};
+
+/// Used to ensure we don't run tests which share global state (such as
+/// `errno`) concurrently. Taking the lock puts that state back to how it
+/// starts.
+///
+/// Some tests are expected to panic with the lock held, so a poisoned lock
+/// is still taken.
+#[cfg(test)]
+fn test_lock() -> std::sync::MutexGuard<'static, ()> {
+	static TEST_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+	let guard = TEST_LOCK
+		.lock()
+		.unwrap_or_else(std::sync::PoisonError::into_inner);
+	errno::set_errno(0);
+	#[cfg(feature = "exit")]
+	exit::reset();
+	#[cfg(feature = "malloc-leaks")]
+	malloc_leaks::reset();
+	#[cfg(feature = "malloc-pools")]
+	malloc_pools::reset();
+	#[cfg(feature = "sbrk")]
+	sbrk::reset();
+	#[cfg(feature = "signal")]
+	itimer::reset();
+	#[cfg(feature = "signal")]
+	signal::reset();
+	guard
+}
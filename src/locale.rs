@@ -0,0 +1,312 @@
+//! Rust implementation of the C library's locale functions (`setlocale`,
+//! `localeconv`, `strcoll`, `strxfrm` and `nl_langinfo`).
+//!
+//! Only the "C" (or "POSIX") locale is supported. As with musl, its multibyte
+//! encoding is UTF-8, so "C.UTF-8" is accepted as another name for it.
+//!
+//! Licensed under the Blue Oak Model Licence 1.0.0
+
+use core::cell::UnsafeCell;
+use core::ffi::{c_char, c_int, CStr};
+
+use crate::strcmp::strcmp;
+use crate::strlen::strlen;
+
+/// The highest locale category number we accept.
+///
+/// newlib numbers its categories 0 to 6, and glibc 0 to 12.
+const LC_MAX: c_int = 12;
+
+/// The name of our one and only locale.
+const LOCALE_NAME: &CStr = c"C";
+
+/// The names `setlocale` accepts for our locale.
+const LOCALE_ALIASES: [&CStr; 5] = [c"C", c"POSIX", c"", c"C.UTF-8", c"C.utf8"];
+
+/// The C `nl_item` type.
+#[allow(non_camel_case_types)]
+pub type nl_item = c_int;
+
+/// Codeset name
+pub const CODESET: nl_item = 0;
+/// Date and time format string
+pub const D_T_FMT: nl_item = 1;
+/// Date format string
+pub const D_FMT: nl_item = 2;
+/// Time format string
+pub const T_FMT: nl_item = 3;
+/// 12-hour time format string
+pub const T_FMT_AMPM: nl_item = 4;
+/// Ante meridiem affix
+pub const AM_STR: nl_item = 5;
+/// Post meridiem affix
+pub const PM_STR: nl_item = 6;
+/// Name of the first day of the week (Sunday)
+pub const DAY_1: nl_item = 7;
+/// Abbreviated name of the first day of the week (Sun)
+pub const ABDAY_1: nl_item = 14;
+/// Name of the first month of the year (January)
+pub const MON_1: nl_item = 21;
+/// Abbreviated name of the first month of the year (Jan)
+pub const ABMON_1: nl_item = 33;
+/// Era description segments
+pub const ERA: nl_item = 45;
+/// Era date format string
+pub const ERA_D_FMT: nl_item = 46;
+/// Alternative symbols for digits
+pub const ALT_DIGITS: nl_item = 47;
+/// Era date and time format string
+pub const ERA_D_T_FMT: nl_item = 48;
+/// Era time format string
+pub const ERA_T_FMT: nl_item = 49;
+/// Radix character
+pub const RADIXCHAR: nl_item = 50;
+/// Thousands separator
+pub const THOUSEP: nl_item = 51;
+/// Affirmative response regular expression
+pub const YESEXPR: nl_item = 52;
+/// Negative response regular expression
+pub const NOEXPR: nl_item = 53;
+/// Currency symbol
+pub const CRNCYSTR: nl_item = 56;
+
+/// The C `struct lconv` type, as returned by `localeconv`.
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug)]
+pub struct lconv {
+	pub decimal_point: *const c_char,
+	pub thousands_sep: *const c_char,
+	pub grouping: *const c_char,
+	pub int_curr_symbol: *const c_char,
+	pub currency_symbol: *const c_char,
+	pub mon_decimal_point: *const c_char,
+	pub mon_thousands_sep: *const c_char,
+	pub mon_grouping: *const c_char,
+	pub positive_sign: *const c_char,
+	pub negative_sign: *const c_char,
+	pub int_frac_digits: c_char,
+	pub frac_digits: c_char,
+	pub p_cs_precedes: c_char,
+	pub p_sep_by_space: c_char,
+	pub n_cs_precedes: c_char,
+	pub n_sep_by_space: c_char,
+	pub p_sign_posn: c_char,
+	pub n_sign_posn: c_char,
+	pub int_p_cs_precedes: c_char,
+	pub int_n_cs_precedes: c_char,
+	pub int_p_sep_by_space: c_char,
+	pub int_n_sep_by_space: c_char,
+	pub int_p_sign_posn: c_char,
+	pub int_n_sign_posn: c_char,
+}
+
+/// Holds our `lconv` in a static.
+///
+/// `localeconv` returns a mutable pointer to it, so C code may write to it.
+/// We never do, so sharing it is as safe as any other C global.
+struct StaticLconv(UnsafeCell<lconv>);
+
+unsafe impl Sync for StaticLconv {}
+
+/// The numeric and monetary formatting conventions of the "C" locale.
+static C_LCONV: StaticLconv = StaticLconv(UnsafeCell::new(lconv {
+	decimal_point: c".".as_ptr(),
+	thousands_sep: c"".as_ptr(),
+	grouping: c"".as_ptr(),
+	int_curr_symbol: c"".as_ptr(),
+	currency_symbol: c"".as_ptr(),
+	mon_decimal_point: c"".as_ptr(),
+	mon_thousands_sep: c"".as_ptr(),
+	mon_grouping: c"".as_ptr(),
+	positive_sign: c"".as_ptr(),
+	negative_sign: c"".as_ptr(),
+	int_frac_digits: c_char::MAX,
+	frac_digits: c_char::MAX,
+	p_cs_precedes: c_char::MAX,
+	p_sep_by_space: c_char::MAX,
+	n_cs_precedes: c_char::MAX,
+	n_sep_by_space: c_char::MAX,
+	p_sign_posn: c_char::MAX,
+	n_sign_posn: c_char::MAX,
+	int_p_cs_precedes: c_char::MAX,
+	int_n_cs_precedes: c_char::MAX,
+	int_p_sep_by_space: c_char::MAX,
+	int_n_sep_by_space: c_char::MAX,
+	int_p_sign_posn: c_char::MAX,
+	int_n_sign_posn: c_char::MAX,
+}));
+
+/// Rust implementation of C library function `setlocale`
+///
+/// Only "C", "POSIX", "C.UTF-8" and "" (the default, which is "C") are
+/// accepted. Anything else returns NULL. The returned name is always "C".
+#[cfg_attr(feature = "setlocale", no_mangle)]
+pub unsafe extern "C" fn setlocale(category: c_int, locale: *const c_char) -> *const c_char {
+	if !(0..=LC_MAX).contains(&category) {
+		return core::ptr::null();
+	}
+	if !locale.is_null() {
+		let locale = CStr::from_ptr(locale);
+		if !LOCALE_ALIASES.contains(&locale) {
+			return core::ptr::null();
+		}
+	}
+	LOCALE_NAME.as_ptr()
+}
+
+/// Rust implementation of C library function `localeconv`
+///
+/// C code should not modify the returned structure, but if it does, later
+/// calls return the modified structure.
+#[cfg_attr(feature = "localeconv", no_mangle)]
+pub extern "C" fn localeconv() -> *mut lconv {
+	C_LCONV.0.get()
+}
+
+/// Rust implementation of C library function `strcoll`
+///
+/// The "C" locale collates strings in byte order, just like `strcmp`.
+#[cfg_attr(feature = "strcoll", no_mangle)]
+pub unsafe extern "C" fn strcoll(s1: *const c_char, s2: *const c_char) -> c_int {
+	strcmp(s1, s2)
+}
+
+/// Rust implementation of C library function `strxfrm`
+///
+/// The "C" locale collates strings in byte order, so this is a copy. Returns
+/// the length of `src`; if that is not less than `n`, `dest` is not written.
+#[cfg_attr(feature = "strxfrm", no_mangle)]
+pub unsafe extern "C" fn strxfrm(dest: *mut c_char, src: *const c_char, n: usize) -> usize {
+	let len = strlen(src);
+	if len < n {
+		core::ptr::copy_nonoverlapping(src, dest, len + 1);
+	}
+	len
+}
+
+/// Rust implementation of C library function `nl_langinfo`
+///
+/// Item numbers follow newlib (and the BSDs). Unknown items give an empty
+/// string.
+#[cfg_attr(feature = "nl_langinfo", no_mangle)]
+pub extern "C" fn nl_langinfo(item: nl_item) -> *const c_char {
+	const DAYS: [&CStr; 7] = [
+		c"Sunday",
+		c"Monday",
+		c"Tuesday",
+		c"Wednesday",
+		c"Thursday",
+		c"Friday",
+		c"Saturday",
+	];
+	const ABDAYS: [&CStr; 7] = [c"Sun", c"Mon", c"Tue", c"Wed", c"Thu", c"Fri", c"Sat"];
+	const MONTHS: [&CStr; 12] = [
+		c"January",
+		c"February",
+		c"March",
+		c"April",
+		c"May",
+		c"June",
+		c"July",
+		c"August",
+		c"September",
+		c"October",
+		c"November",
+		c"December",
+	];
+	const ABMONTHS: [&CStr; 12] = [
+		c"Jan", c"Feb", c"Mar", c"Apr", c"May", c"Jun", c"Jul", c"Aug", c"Sep", c"Oct", c"Nov",
+		c"Dec",
+	];
+
+	let value = match item {
+		CODESET => c"UTF-8",
+		D_T_FMT => c"%a %b %e %H:%M:%S %Y",
+		D_FMT => c"%m/%d/%y",
+		T_FMT => c"%H:%M:%S",
+		T_FMT_AMPM => c"%I:%M:%S %p",
+		AM_STR => c"AM",
+		PM_STR => c"PM",
+		_ if (DAY_1..ABDAY_1).contains(&item) => DAYS[(item - DAY_1) as usize],
+		_ if (ABDAY_1..MON_1).contains(&item) => ABDAYS[(item - ABDAY_1) as usize],
+		_ if (MON_1..ABMON_1).contains(&item) => MONTHS[(item - MON_1) as usize],
+		_ if (ABMON_1..ERA).contains(&item) => ABMONTHS[(item - ABMON_1) as usize],
+		RADIXCHAR => c".",
+		YESEXPR => c"^[yY]",
+		NOEXPR => c"^[nN]",
+		// ERA, ALT_DIGITS, THOUSEP, CRNCYSTR, etc, and unknown items
+		_ => c"",
+	};
+	value.as_ptr()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn setlocale_accepts_c() {
+		for name in LOCALE_ALIASES {
+			let result = unsafe { setlocale(0, name.as_ptr()) };
+			assert_eq!(unsafe { CStr::from_ptr(result) }, c"C");
+		}
+		let result = unsafe { setlocale(0, core::ptr::null()) };
+		assert_eq!(unsafe { CStr::from_ptr(result) }, c"C");
+	}
+
+	#[test]
+	fn setlocale_rejects_others() {
+		assert!(unsafe { setlocale(0, c"en_GB.UTF-8".as_ptr()) }.is_null());
+		assert!(unsafe { setlocale(-1, c"C".as_ptr()) }.is_null());
+		assert!(unsafe { setlocale(LC_MAX + 1, c"C".as_ptr()) }.is_null());
+	}
+
+	#[test]
+	fn localeconv_is_c() {
+		let conv = unsafe { &*localeconv() };
+		assert_eq!(unsafe { CStr::from_ptr(conv.decimal_point) }, c".");
+		assert_eq!(unsafe { CStr::from_ptr(conv.thousands_sep) }, c"");
+		assert_eq!(conv.frac_digits, c_char::MAX);
+	}
+
+	#[test]
+	fn strcoll_is_byte_order() {
+		unsafe {
+			assert_eq!(strcoll(c"abc".as_ptr(), c"abc".as_ptr()), 0);
+			assert!(strcoll(c"B".as_ptr(), c"a".as_ptr()) < 0);
+			assert!(strcoll(c"abcd".as_ptr(), c"abc".as_ptr()) > 0);
+		}
+	}
+
+	#[test]
+	fn strxfrm_copies() {
+		let mut dest = [0x55 as c_char; 8];
+		let len = unsafe { strxfrm(dest.as_mut_ptr(), c"hello".as_ptr(), dest.len()) };
+		assert_eq!(len, 5);
+		assert_eq!(unsafe { CStr::from_ptr(dest.as_ptr()) }, c"hello");
+		// Too small, so nothing is written
+		let mut small = [0x55 as c_char; 4];
+		let len = unsafe { strxfrm(small.as_mut_ptr(), c"hello".as_ptr(), small.len()) };
+		assert_eq!(len, 5);
+		assert_eq!(small, [0x55; 4]);
+		assert_eq!(
+			unsafe { strxfrm(core::ptr::null_mut(), c"hi".as_ptr(), 0) },
+			2
+		);
+	}
+
+	#[test]
+	fn langinfo() {
+		let get = |item| unsafe { CStr::from_ptr(nl_langinfo(item)) };
+		assert_eq!(get(CODESET), c"UTF-8");
+		assert_eq!(get(RADIXCHAR), c".");
+		assert_eq!(get(DAY_1), c"Sunday");
+		assert_eq!(get(DAY_1 + 6), c"Saturday");
+		assert_eq!(get(ABDAY_1 + 1), c"Mon");
+		assert_eq!(get(MON_1 + 11), c"December");
+		assert_eq!(get(ABMON_1), c"Jan");
+		assert_eq!(get(ERA), c"");
+		assert_eq!(get(1000), c"");
+	}
+}
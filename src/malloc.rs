@@ -1,76 +1,502 @@
-//! Rust implementation of C library function `malloc`, `calloc`, `realloc`, and `free`.
+//! Rust implementation of C library function `malloc`, `calloc`, `realloc`, `free`,
+//! `reallocarray`, `aligned_alloc`, `posix_memalign`, `memalign`, `valloc` and
+//! `malloc_usable_size`.
+//!
+//! Memory comes from the Rust global allocator, or from our own heap if the
+//! `heap` feature is enabled. With the `malloc-stats` feature, we also keep
+//! count of what is allocated, for [`heap_stats`]. With the `malloc-debug`
+//! feature, blocks are guarded and checked (see `malloc_debug.rs`). If
+//! memory runs out, the out-of-memory handler (see `malloc_oom.rs`) can ask
+//! for the allocation to be retried. With the `malloc-leaks` feature, live
+//! allocations are recorded (see `malloc_leaks.rs`). With the
+//! `malloc-pools` feature, small allocations come from fixed-size pools
+//! where possible (see `malloc_pools.rs`).
 //!
 //! Copyright (c) Gyungmin Myung <gmmyung@kaist.ac.kr>
 //! This file is licensed under the Blue Oak Model Licence 1.0.0
 
+use core::alloc::Layout;
+use core::ffi::c_int;
+
+use crate::errno::{errno, set_errno, EINVAL, ENOMEM};
+#[cfg(feature = "malloc-debug")]
+use crate::malloc_debug::{self, HeapViolation};
+#[cfg(feature = "malloc-leaks")]
+use crate::malloc_leaks;
+use crate::malloc_oom;
+#[cfg(feature = "malloc-pools")]
+use crate::malloc_pools;
+
+// Allocate from the Rust global allocator, or from our own heap.
+#[cfg(not(feature = "heap"))]
 extern crate alloc;
+#[cfg(feature = "heap")]
+use crate::heap as backend;
+#[cfg(not(feature = "heap"))]
+use alloc::alloc as backend;
 
 // The maximum alignment of any fundamental type. Equivalent to max_align_t
-const MAX_ALIGN: usize = 16;
+pub(crate) const MAX_ALIGN: usize = 16;
+
+/// The alignment `valloc` uses.
+const PAGE_SIZE: usize = 4096;
+
+/// The number of guard bytes after every block.
+#[cfg(feature = "malloc-debug")]
+pub(crate) const TAIL: usize = malloc_debug::GUARD_SIZE;
+#[cfg(not(feature = "malloc-debug"))]
+pub(crate) const TAIL: usize = 0;
+
+/// The offset of the pointer we return into a `MAX_ALIGN` aligned allocation.
+#[cfg(feature = "malloc-pools")]
+pub(crate) const MIN_OFFSET: usize = core::mem::size_of::<Header>().next_multiple_of(MAX_ALIGN);
+
+/// The header stored immediately before every pointer we return.
+///
+/// The underlying allocation is aligned to `align`, and the pointer we return
+/// is [`Header::offset`] bytes into it (which is usually just `align`). The
+/// header therefore sits at the end of that leading padding.
+#[repr(C)]
+struct Header {
+	/// The number of bytes the caller asked for
+	size: usize,
+	/// The alignment of the underlying allocation (at least `MAX_ALIGN`)
+	align: usize,
+	/// `LIVE_CANARY`, or `FREED_CANARY` once the block is freed
+	#[cfg(feature = "malloc-debug")]
+	canary: usize,
+}
+
+impl Header {
+	fn new(size: usize, align: usize) -> Header {
+		Header {
+			size,
+			align,
+			#[cfg(feature = "malloc-debug")]
+			canary: malloc_debug::LIVE_CANARY,
+		}
+	}
+
+	/// Get the header for a pointer we returned.
+	unsafe fn of(ptr: *mut u8) -> *mut Header {
+		(ptr as *mut Header).sub(1)
+	}
+
+	/// Get the header for a pointer we returned, or `None` if it's NULL.
+	unsafe fn read(ptr: *mut u8) -> Option<Header> {
+		if ptr.is_null() {
+			None
+		} else {
+			Some(unsafe { Header::of(ptr).read() })
+		}
+	}
+
+	/// Get the offset from the start of the underlying allocation to the
+	/// pointer we return - the smallest multiple of `align` with room for the
+	/// header.
+	fn offset(&self) -> usize {
+		core::mem::size_of::<Header>().next_multiple_of(self.align)
+	}
+
+	/// Get the layout of the underlying allocation, or `None` if it would be
+	/// too large.
+	fn layout(&self) -> Option<Layout> {
+		let total = self.size.checked_add(self.offset())?.checked_add(TAIL)?;
+		Layout::from_size_align(total, self.align).ok()
+	}
+}
+
+/// Statistics about the memory allocated with `malloc` and friends.
+///
+/// Sizes are the sizes callers asked for, not including our header or any
+/// padding.
+#[cfg(feature = "malloc-stats")]
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+	/// The number of bytes currently allocated
+	pub live_bytes: usize,
+	/// The largest `live_bytes` has ever been
+	pub peak_bytes: usize,
+	/// The number of allocations not yet freed
+	pub live_allocations: usize,
+	/// The number of times allocation has failed for lack of memory
+	pub failed_allocations: usize,
+}
+
+#[cfg(feature = "malloc-stats")]
+mod stats {
+	use super::HeapStats;
+	use portable_atomic::{AtomicUsize, Ordering};
+
+	/// The counters behind [`HeapStats`].
+	pub(super) struct Counters {
+		live_bytes: AtomicUsize,
+		peak_bytes: AtomicUsize,
+		live_allocations: AtomicUsize,
+		failed_allocations: AtomicUsize,
+	}
+
+	impl Counters {
+		const fn new() -> Counters {
+			Counters {
+				live_bytes: AtomicUsize::new(0),
+				peak_bytes: AtomicUsize::new(0),
+				live_allocations: AtomicUsize::new(0),
+				failed_allocations: AtomicUsize::new(0),
+			}
+		}
+
+		pub(super) fn allocated(&self, size: usize) {
+			self.live_allocations.fetch_add(1, Ordering::Relaxed);
+			self.grown(size);
+		}
+
+		pub(super) fn freed(&self, size: usize) {
+			self.live_allocations.fetch_sub(1, Ordering::Relaxed);
+			self.shrunk(size);
+		}
+
+		pub(super) fn grown(&self, size: usize) {
+			let live = self.live_bytes.fetch_add(size, Ordering::Relaxed) + size;
+			self.peak_bytes.fetch_max(live, Ordering::Relaxed);
+		}
+
+		pub(super) fn shrunk(&self, size: usize) {
+			self.live_bytes.fetch_sub(size, Ordering::Relaxed);
+		}
+
+		pub(super) fn failed(&self) {
+			self.failed_allocations.fetch_add(1, Ordering::Relaxed);
+		}
+
+		pub(super) fn snapshot(&self) -> HeapStats {
+			HeapStats {
+				live_bytes: self.live_bytes.load(Ordering::Relaxed),
+				peak_bytes: self.peak_bytes.load(Ordering::Relaxed),
+				live_allocations: self.live_allocations.load(Ordering::Relaxed),
+				failed_allocations: self.failed_allocations.load(Ordering::Relaxed),
+			}
+		}
+	}
+
+	/// Get the counters.
+	pub(super) fn counters() -> &'static Counters {
+		static COUNTERS: Counters = Counters::new();
+		&COUNTERS
+	}
+}
+
+/// Record that an allocation failed for lack of memory, and return NULL.
+fn out_of_memory() -> *mut u8 {
+	set_errno(ENOMEM);
+	#[cfg(feature = "malloc-stats")]
+	stats::counters().failed();
+	core::ptr::null_mut()
+}
+
+/// Allocate an underlying allocation, from a pool if possible.
+unsafe fn raw_alloc(layout: Layout, zeroed: bool) -> *mut u8 {
+	#[cfg(feature = "malloc-pools")]
+	if layout.align() <= MAX_ALIGN {
+		let base = malloc_pools::alloc(layout.size());
+		if !base.is_null() {
+			if zeroed {
+				unsafe { core::ptr::write_bytes(base, 0, layout.size()) };
+			}
+			return base;
+		}
+	}
+	if zeroed {
+		unsafe { backend::alloc_zeroed(layout) }
+	} else {
+		unsafe { backend::alloc(layout) }
+	}
+}
+
+/// Resize an underlying allocation, which may be in a pool.
+unsafe fn raw_realloc(base: *mut u8, old_layout: Layout, new_layout: Layout) -> *mut u8 {
+	#[cfg(feature = "malloc-pools")]
+	if let Some(capacity) = malloc_pools::capacity(base) {
+		if new_layout.size() <= capacity {
+			return base;
+		}
+		let new_base = unsafe { raw_alloc(new_layout, false) };
+		if !new_base.is_null() {
+			unsafe {
+				core::ptr::copy_nonoverlapping(base, new_base, old_layout.size());
+				malloc_pools::dealloc(base);
+			}
+		}
+		return new_base;
+	}
+	unsafe { backend::realloc(base, old_layout, new_layout.size()) }
+}
+
+/// Free an underlying allocation, which may be in a pool.
+unsafe fn raw_dealloc(base: *mut u8, layout: Layout) {
+	#[cfg(feature = "malloc-pools")]
+	if unsafe { malloc_pools::dealloc(base) } {
+		return;
+	}
+	unsafe { backend::dealloc(base, layout) }
+}
+
+/// Allocate `size` bytes, aligned to `align` (which must be a power of two).
+///
+/// Sets `errno` to `ENOMEM` on failure.
+unsafe fn alloc_aligned(size: usize, align: usize, zeroed: bool) -> *mut u8 {
+	let header = Header::new(size, align.max(MAX_ALIGN));
+	let Some(layout) = header.layout() else {
+		return out_of_memory();
+	};
+	let base = loop {
+		let base = unsafe { raw_alloc(layout, zeroed) };
+		if !base.is_null() || !malloc_oom::should_retry(size) {
+			break base;
+		}
+	};
+	if base.is_null() {
+		return out_of_memory();
+	}
+	#[cfg(feature = "malloc-stats")]
+	stats::counters().allocated(size);
+	let ptr = unsafe { base.add(header.offset()) };
+	unsafe { Header::of(ptr).write(header) };
+	#[cfg(feature = "malloc-debug")]
+	unsafe {
+		if !zeroed {
+			core::ptr::write_bytes(ptr, malloc_debug::FRESH_BYTE, size);
+		}
+		malloc_debug::write_guard(ptr, size);
+	}
+	#[cfg(feature = "malloc-leaks")]
+	malloc_leaks::record(ptr, size);
+	ptr
+}
+
+/// Check the guards around a block passed to `free` or `realloc`, reporting
+/// any problems.
+///
+/// Returns `false` if the block must be left alone.
+#[cfg(feature = "malloc-debug")]
+unsafe fn check_block(ptr: *mut u8) -> bool {
+	let header = unsafe { Header::of(ptr).read() };
+	let result = malloc_debug::check_canary(header.canary)
+		.and_then(|()| unsafe { malloc_debug::check_guard(ptr, header.size) });
+	match result {
+		Ok(()) => true,
+		Err(violation) => {
+			malloc_debug::report(ptr, violation);
+			violation == HeapViolation::Overrun
+		}
+	}
+}
 
 /// Rust implementation of C library function `malloc`
 ///
+/// If there isn't enough memory, the out-of-memory handler (if any) is called
+/// and may ask for the allocation to be retried. Otherwise NULL is returned
+/// and `errno` is set to `ENOMEM`. Sizes too large to ever allocate fail
+/// without calling the handler.
+///
 /// See [malloc](https://linux.die.net/man/3/malloc) for alignment details.
 #[cfg_attr(not(test), no_mangle)]
 pub unsafe extern "C" fn malloc(size: usize) -> *mut u8 {
-	// size + MAX_ALIGN for to store the size of the allocated memory.
-	let layout = alloc::alloc::Layout::from_size_align(size + MAX_ALIGN, MAX_ALIGN).unwrap();
-	let ptr = unsafe { alloc::alloc::alloc(layout) };
-	if ptr.is_null() {
-		return ptr;
-	}
-	unsafe {
-		*(ptr as *mut usize) = size;
-	}
-	unsafe { ptr.add(MAX_ALIGN) }
+	alloc_aligned(size, MAX_ALIGN, false)
 }
 
 /// Rust implementation of C library function `calloc`
 ///
+/// Returns NULL, and sets `errno` to `ENOMEM`, if `nmemb * size` overflows.
+///
 /// See [calloc](https://linux.die.net/man/3/calloc) for alignment details.
 #[cfg_attr(not(test), no_mangle)]
 pub unsafe extern "C" fn calloc(nmemb: usize, size: usize) -> *mut u8 {
-	let total_size = nmemb * size;
-	let layout = alloc::alloc::Layout::from_size_align(total_size + MAX_ALIGN, MAX_ALIGN).unwrap();
-	let ptr = unsafe { alloc::alloc::alloc_zeroed(layout) };
-	if ptr.is_null() {
-		return ptr;
-	}
-	unsafe {
-		*(ptr as *mut usize) = total_size;
-	}
-	unsafe { ptr.add(MAX_ALIGN) }
+	let Some(total_size) = nmemb.checked_mul(size) else {
+		return out_of_memory();
+	};
+	alloc_aligned(total_size, MAX_ALIGN, true)
 }
 
 /// Rust implementation of C library function `realloc`
 ///
+/// The new memory has the same alignment as the old memory. On failure, NULL
+/// is returned, `errno` is set to `ENOMEM` and the old memory is untouched.
+///
+/// What `realloc(ptr, 0)` does is implementation-defined. By default `ptr`
+/// is resized to zero bytes and a non-NULL pointer is returned, which must be
+/// freed. With the `realloc-zero-frees` feature, `ptr` is freed and NULL is
+/// returned (as in glibc).
+///
 /// See [realloc](https://linux.die.net/man/3/realloc) for alignment details.
 #[cfg_attr(not(test), no_mangle)]
 pub unsafe extern "C" fn realloc(ptr: *mut u8, size: usize) -> *mut u8 {
 	if ptr.is_null() {
 		return malloc(size);
 	}
-	let old_size = unsafe { *(ptr.sub(MAX_ALIGN) as *mut usize) };
-	let layout = alloc::alloc::Layout::from_size_align(old_size + MAX_ALIGN, MAX_ALIGN).unwrap();
-	let new_ptr = unsafe { alloc::alloc::realloc(ptr.sub(MAX_ALIGN), layout, size + MAX_ALIGN) };
-	if new_ptr.is_null() {
-		return new_ptr;
+	if cfg!(feature = "realloc-zero-frees") && size == 0 {
+		free(ptr);
+		return core::ptr::null_mut();
+	}
+	#[cfg(feature = "malloc-debug")]
+	if !check_block(ptr) {
+		set_errno(EINVAL);
+		return core::ptr::null_mut();
 	}
+	let old_header = unsafe { Header::of(ptr).read() };
+	let new_header = Header::new(size, old_header.align);
+	let (Some(old_layout), Some(new_layout)) = (old_header.layout(), new_header.layout()) else {
+		return out_of_memory();
+	};
+	let base = unsafe { ptr.sub(old_header.offset()) };
+	let new_base = loop {
+		let new_base = unsafe { raw_realloc(base, old_layout, new_layout) };
+		if !new_base.is_null() || !malloc_oom::should_retry(size) {
+			break new_base;
+		}
+	};
+	if new_base.is_null() {
+		return out_of_memory();
+	}
+	#[cfg(feature = "malloc-stats")]
+	if size > old_header.size {
+		stats::counters().grown(size - old_header.size);
+	} else {
+		stats::counters().shrunk(old_header.size - size);
+	}
+	let new_ptr = unsafe { new_base.add(new_header.offset()) };
+	unsafe { Header::of(new_ptr).write(new_header) };
+	#[cfg(feature = "malloc-debug")]
 	unsafe {
-		*(new_ptr as *mut usize) = size;
+		if size > old_header.size {
+			let fresh = new_ptr.add(old_header.size);
+			core::ptr::write_bytes(fresh, malloc_debug::FRESH_BYTE, size - old_header.size);
+		}
+		malloc_debug::write_guard(new_ptr, size);
 	}
-	unsafe { new_ptr.add(MAX_ALIGN) }
+	#[cfg(feature = "malloc-leaks")]
+	malloc_leaks::update(ptr, new_ptr, size);
+	new_ptr
+}
+
+/// Rust implementation of C library function `reallocarray`
+///
+/// As `realloc(ptr, nmemb * size)`, except that if the multiplication
+/// overflows, NULL is returned, `errno` is set to `ENOMEM` and the old memory
+/// is untouched.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn reallocarray(ptr: *mut u8, nmemb: usize, size: usize) -> *mut u8 {
+	let Some(total_size) = nmemb.checked_mul(size) else {
+		return out_of_memory();
+	};
+	realloc(ptr, total_size)
 }
 
 /// Rust implementation of C library function `free`
+///
+/// Accepts pointers from any of the allocation functions in this module.
 #[cfg_attr(not(test), no_mangle)]
 pub unsafe extern "C" fn free(ptr: *mut u8) {
-	if ptr.is_null() {
+	#[cfg(feature = "malloc-debug")]
+	if !ptr.is_null() && !check_block(ptr) {
+		return;
+	}
+	let Some(header) = (unsafe { Header::read(ptr) }) else {
 		return;
+	};
+	#[cfg(feature = "malloc-stats")]
+	stats::counters().freed(header.size);
+	#[cfg(feature = "malloc-leaks")]
+	malloc_leaks::remove(ptr);
+	#[cfg(feature = "malloc-debug")]
+	unsafe {
+		core::ptr::write_bytes(ptr, malloc_debug::FREED_BYTE, header.size);
+		(*Header::of(ptr)).canary = malloc_debug::FREED_CANARY;
+	}
+	// The layout was valid when we allocated the memory, so this can't fail
+	if let Some(layout) = header.layout() {
+		unsafe { raw_dealloc(ptr.sub(header.offset()), layout) };
 	}
-	let old_size = unsafe { *(ptr.sub(MAX_ALIGN) as *mut usize) };
-	let layout = alloc::alloc::Layout::from_size_align(old_size + MAX_ALIGN, MAX_ALIGN).unwrap();
-	unsafe { alloc::alloc::dealloc(ptr.sub(MAX_ALIGN), layout) };
+}
+
+/// Rust implementation of C library function `aligned_alloc`
+///
+/// `alignment` must be a power of two, otherwise NULL is returned and
+/// `errno` is set to `EINVAL`.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn aligned_alloc(alignment: usize, size: usize) -> *mut u8 {
+	if !alignment.is_power_of_two() {
+		set_errno(EINVAL);
+		return core::ptr::null_mut();
+	}
+	alloc_aligned(size, alignment, false)
+}
+
+/// Rust implementation of C library function `memalign`
+///
+/// `alignment` must be a power of two, otherwise NULL is returned.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn memalign(alignment: usize, size: usize) -> *mut u8 {
+	aligned_alloc(alignment, size)
+}
+
+/// Rust implementation of C library function `valloc`
+///
+/// Bare-metal systems don't have pages, so this aligns to 4096 bytes.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn valloc(size: usize) -> *mut u8 {
+	alloc_aligned(size, PAGE_SIZE, false)
+}
+
+/// Rust implementation of C library function `posix_memalign`
+///
+/// `alignment` must be a power of two multiple of `sizeof(void *)`. Returns
+/// 0 on success, or `EINVAL` or `ENOMEM` on failure (leaving `*memptr`
+/// untouched).
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn posix_memalign(
+	memptr: *mut *mut u8,
+	alignment: usize,
+	size: usize,
+) -> c_int {
+	if !alignment.is_power_of_two() || alignment % core::mem::size_of::<*mut u8>() != 0 {
+		return EINVAL;
+	}
+	let saved_errno = errno();
+	let ptr = alloc_aligned(size, alignment, false);
+	if ptr.is_null() {
+		// posix_memalign reports errors by return value, not errno
+		set_errno(saved_errno);
+		return ENOMEM;
+	}
+	unsafe { *memptr = ptr };
+	0
+}
+
+/// Rust implementation of C library function `malloc_usable_size`
+///
+/// Returns the number of bytes which can be used at `ptr` (which must have
+/// come from one of the allocation functions in this module), or 0 if `ptr`
+/// is NULL.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn malloc_usable_size(ptr: *mut u8) -> usize {
+	unsafe { Header::read(ptr) }.map_or(0, |header| header.size)
+}
+
+/// Get statistics about the memory allocated with `malloc` and friends.
+///
+/// Requires the `malloc-stats` feature.
+#[cfg(feature = "malloc-stats")]
+pub fn heap_stats() -> HeapStats {
+	stats::counters().snapshot()
+}
+
+/// C interface to [`heap_stats`], in the style of `mallinfo`.
+#[cfg(feature = "malloc-stats")]
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn tinyrlibc_mallinfo() -> HeapStats {
+	heap_stats()
 }
 
 #[cfg(test)]
@@ -79,10 +505,11 @@ mod test {
 
 	#[test]
 	fn test_malloc() {
+		let _guard = crate::test_lock();
 		let ptr = unsafe { malloc(10) };
 		assert!(!ptr.is_null());
 		unsafe {
-			assert_eq!(*(ptr.sub(MAX_ALIGN) as *mut usize), 10);
+			assert_eq!((*Header::of(ptr)).size, 10);
 			(0..10).for_each(|i| {
 				*ptr.add(i) = i as u8;
 			});
@@ -95,10 +522,11 @@ mod test {
 
 	#[test]
 	fn test_calloc() {
+		let _guard = crate::test_lock();
 		let ptr = unsafe { calloc(10, 10) };
 		assert!(!ptr.is_null());
 		unsafe {
-			assert_eq!(*(ptr.sub(MAX_ALIGN) as *mut usize), 100);
+			assert_eq!((*Header::of(ptr)).size, 100);
 			(0..100).for_each(|i| {
 				assert_eq!(*ptr.add(i), 0);
 			});
@@ -114,10 +542,11 @@ mod test {
 
 	#[test]
 	fn test_realloc() {
+		let _guard = crate::test_lock();
 		let ptr = unsafe { malloc(10) };
 		assert!(!ptr.is_null());
 		unsafe {
-			assert_eq!(*(ptr.sub(MAX_ALIGN) as *mut usize), 10);
+			assert_eq!((*Header::of(ptr)).size, 10);
 			(0..10).for_each(|i| {
 				*ptr.add(i) = i as u8;
 			});
@@ -128,7 +557,7 @@ mod test {
 		let ptr = unsafe { realloc(ptr, 20) };
 		assert!(!ptr.is_null());
 		unsafe {
-			assert_eq!(*(ptr.sub(MAX_ALIGN) as *mut usize), 20);
+			assert_eq!((*Header::of(ptr)).size, 20);
 			(0..10).for_each(|i| {
 				assert_eq!(*ptr.add(i), i as u8);
 			});
@@ -141,4 +570,449 @@ mod test {
 		}
 		unsafe { free(ptr) };
 	}
+
+	#[test]
+	fn test_aligned_alloc() {
+		let _guard = crate::test_lock();
+		for alignment in [1, 8, 16, 32, 64, 256, 4096] {
+			let ptr = unsafe { aligned_alloc(alignment, 100) };
+			assert!(!ptr.is_null());
+			assert_eq!(ptr as usize % alignment, 0);
+			unsafe {
+				assert_eq!((*Header::of(ptr)).size, 100);
+				core::ptr::write_bytes(ptr, 0xAA, 100);
+				free(ptr);
+			}
+		}
+		assert!(unsafe { aligned_alloc(24, 100) }.is_null());
+	}
+
+	#[test]
+	fn test_posix_memalign() {
+		let _guard = crate::test_lock();
+		let mut ptr = core::ptr::null_mut();
+		assert_eq!(unsafe { posix_memalign(&mut ptr, 64, 10) }, 0);
+		assert_eq!(ptr as usize % 64, 0);
+		unsafe { free(ptr) };
+
+		let mut ptr = core::ptr::null_mut();
+		assert_eq!(unsafe { posix_memalign(&mut ptr, 2, 10) }, EINVAL);
+		assert_eq!(unsafe { posix_memalign(&mut ptr, 48, 10) }, EINVAL);
+		assert!(ptr.is_null());
+	}
+
+	#[test]
+	fn test_memalign_and_valloc() {
+		let _guard = crate::test_lock();
+		let ptr = unsafe { memalign(128, 10) };
+		assert_eq!(ptr as usize % 128, 0);
+		unsafe { free(ptr) };
+		let ptr = unsafe { valloc(10) };
+		assert_eq!(ptr as usize % PAGE_SIZE, 0);
+		unsafe { free(ptr) };
+	}
+
+	#[test]
+	fn test_realloc_keeps_alignment() {
+		let _guard = crate::test_lock();
+		let ptr = unsafe { aligned_alloc(64, 16) };
+		unsafe { core::ptr::write_bytes(ptr, 0x5A, 16) };
+		let ptr = unsafe { realloc(ptr, 10000) };
+		assert!(!ptr.is_null());
+		assert_eq!(ptr as usize % 64, 0);
+		unsafe {
+			assert_eq!((*Header::of(ptr)).size, 10000);
+			assert_eq!((*Header::of(ptr)).align, 64);
+			assert_eq!(*ptr.add(15), 0x5A);
+			free(ptr);
+		}
+	}
+
+	#[test]
+	fn test_malloc_too_large() {
+		let _guard = crate::test_lock();
+		for size in [usize::MAX, usize::MAX - MAX_ALIGN + 1, isize::MAX as usize] {
+			set_errno(0);
+			assert!(unsafe { malloc(size) }.is_null());
+			assert_eq!(errno(), ENOMEM);
+		}
+	}
+
+	#[test]
+	fn test_calloc_overflow() {
+		let _guard = crate::test_lock();
+		set_errno(0);
+		assert!(unsafe { calloc(usize::MAX / 2, 3) }.is_null());
+		assert_eq!(errno(), ENOMEM);
+		set_errno(0);
+		assert!(unsafe { calloc(1 << (usize::BITS / 2), 1 << (usize::BITS / 2)) }.is_null());
+		assert_eq!(errno(), ENOMEM);
+	}
+
+	#[test]
+	fn test_realloc_too_large() {
+		let _guard = crate::test_lock();
+		let ptr = unsafe { malloc(10) };
+		unsafe { core::ptr::write_bytes(ptr, 0x33, 10) };
+		for size in [usize::MAX, isize::MAX as usize] {
+			set_errno(0);
+			assert!(unsafe { realloc(ptr, size) }.is_null());
+			assert_eq!(errno(), ENOMEM);
+		}
+		// The old block is still valid
+		unsafe {
+			assert_eq!((*Header::of(ptr)).size, 10);
+			assert_eq!(*ptr.add(9), 0x33);
+			free(ptr);
+		}
+	}
+
+	#[test]
+	fn test_reallocarray() {
+		let _guard = crate::test_lock();
+		let ptr = unsafe { reallocarray(core::ptr::null_mut(), 4, 5) };
+		assert!(!ptr.is_null());
+		unsafe {
+			assert_eq!((*Header::of(ptr)).size, 20);
+			core::ptr::write_bytes(ptr, 0x77, 20);
+		}
+		set_errno(0);
+		assert!(unsafe { reallocarray(ptr, usize::MAX, 2) }.is_null());
+		assert_eq!(errno(), ENOMEM);
+		let ptr = unsafe { reallocarray(ptr, 8, 5) };
+		assert!(!ptr.is_null());
+		unsafe {
+			assert_eq!((*Header::of(ptr)).size, 40);
+			assert_eq!(*ptr.add(19), 0x77);
+			free(ptr);
+		}
+	}
+
+	#[test]
+	#[cfg(not(feature = "realloc-zero-frees"))]
+	fn test_realloc_zero() {
+		let _guard = crate::test_lock();
+		let ptr = unsafe { malloc(10) };
+		let ptr = unsafe { realloc(ptr, 0) };
+		assert!(!ptr.is_null());
+		unsafe {
+			assert_eq!((*Header::of(ptr)).size, 0);
+			free(ptr);
+		}
+	}
+
+	#[test]
+	#[cfg(feature = "realloc-zero-frees")]
+	fn test_realloc_zero() {
+		let _guard = crate::test_lock();
+		let ptr = unsafe { malloc(10) };
+		assert!(unsafe { realloc(ptr, 0) }.is_null());
+	}
+
+	#[test]
+	fn test_aligned_alloc_errno() {
+		let _guard = crate::test_lock();
+		set_errno(0);
+		assert!(unsafe { aligned_alloc(24, 100) }.is_null());
+		assert_eq!(errno(), EINVAL);
+	}
+
+	#[test]
+	fn test_malloc_usable_size() {
+		let _guard = crate::test_lock();
+		assert_eq!(unsafe { malloc_usable_size(core::ptr::null_mut()) }, 0);
+		let ptr = unsafe { malloc(37) };
+		assert_eq!(unsafe { malloc_usable_size(ptr) }, 37);
+		let ptr = unsafe { realloc(ptr, 1000) };
+		assert_eq!(unsafe { malloc_usable_size(ptr) }, 1000);
+		unsafe { free(ptr) };
+	}
+
+	#[test]
+	#[cfg(feature = "malloc-stats")]
+	fn test_heap_stats() {
+		let _guard = crate::test_lock();
+		let before = heap_stats();
+		let a = unsafe { malloc(100) };
+		let b = unsafe { calloc(10, 10) };
+		let stats = heap_stats();
+		assert_eq!(stats.live_bytes, before.live_bytes + 200);
+		assert_eq!(stats.live_allocations, before.live_allocations + 2);
+		assert!(stats.peak_bytes >= stats.live_bytes);
+
+		let a = unsafe { realloc(a, 300) };
+		assert_eq!(heap_stats().live_bytes, before.live_bytes + 400);
+		let a = unsafe { realloc(a, 50) };
+		assert_eq!(heap_stats().live_bytes, before.live_bytes + 150);
+
+		unsafe {
+			free(a);
+			free(b);
+		}
+		let after = heap_stats();
+		assert_eq!(after.live_bytes, before.live_bytes);
+		assert_eq!(after.live_allocations, before.live_allocations);
+		assert!(after.peak_bytes >= before.live_bytes + 400);
+		assert_eq!(tinyrlibc_mallinfo(), after);
+	}
+
+	#[test]
+	#[cfg(feature = "malloc-stats")]
+	fn test_heap_stats_failures() {
+		let _guard = crate::test_lock();
+		let before = heap_stats();
+		assert!(unsafe { malloc(usize::MAX) }.is_null());
+		assert!(unsafe { calloc(usize::MAX, 2) }.is_null());
+		let ptr = unsafe { malloc(8) };
+		assert!(unsafe { realloc(ptr, usize::MAX) }.is_null());
+		unsafe { free(ptr) };
+		let after = heap_stats();
+		assert_eq!(after.failed_allocations, before.failed_allocations + 3);
+		assert_eq!(after.live_bytes, before.live_bytes);
+	}
+
+	#[cfg(feature = "malloc-debug")]
+	std::thread_local! {
+		static VIOLATIONS: core::cell::RefCell<Vec<(*mut u8, HeapViolation)>> = const { core::cell::RefCell::new(Vec::new()) };
+	}
+
+	#[cfg(feature = "malloc-debug")]
+	extern "C" fn record_violation(ptr: *mut u8, violation: HeapViolation) {
+		VIOLATIONS.with(|violations| violations.borrow_mut().push((ptr, violation)));
+	}
+
+	/// Run `f` with violations recorded rather than panicking, returning them.
+	#[cfg(feature = "malloc-debug")]
+	fn violations_in(f: impl FnOnce()) -> Vec<(*mut u8, HeapViolation)> {
+		let old = malloc_debug::tinyrlibc_set_heap_violation_handler(Some(record_violation));
+		f();
+		malloc_debug::tinyrlibc_set_heap_violation_handler(old);
+		VIOLATIONS.with(|violations| violations.take())
+	}
+
+	#[test]
+	#[cfg(feature = "malloc-debug")]
+	fn test_debug_fill() {
+		let _guard = crate::test_lock();
+		let ptr = unsafe { malloc(32) };
+		assert!((0..32).all(|i| unsafe { *ptr.add(i) } == malloc_debug::FRESH_BYTE));
+		unsafe { core::ptr::write_bytes(ptr, 0, 32) };
+		let ptr = unsafe { realloc(ptr, 64) };
+		assert!((0..32).all(|i| unsafe { *ptr.add(i) } == 0));
+		assert!((32..64).all(|i| unsafe { *ptr.add(i) } == malloc_debug::FRESH_BYTE));
+		unsafe { free(ptr) };
+
+		let ptr = unsafe { calloc(4, 8) };
+		assert!((0..32).all(|i| unsafe { *ptr.add(i) } == 0));
+		unsafe { free(ptr) };
+	}
+
+	#[test]
+	#[cfg(feature = "malloc-debug")]
+	fn test_debug_clean() {
+		let _guard = crate::test_lock();
+		let violations = violations_in(|| unsafe {
+			let ptr = malloc(10);
+			core::ptr::write_bytes(ptr, 1, 10);
+			let ptr = realloc(ptr, 5);
+			core::ptr::write_bytes(ptr, 1, 5);
+			let ptr = aligned_alloc(256, 3);
+			core::ptr::write_bytes(ptr, 1, 3);
+			free(ptr);
+		});
+		assert_eq!(violations, []);
+	}
+
+	#[test]
+	#[cfg(feature = "malloc-debug")]
+	fn test_debug_overrun() {
+		let _guard = crate::test_lock();
+		let mut bad = core::ptr::null_mut();
+		let violations = violations_in(|| unsafe {
+			bad = malloc(10);
+			*bad.add(10) = 0;
+			free(bad);
+		});
+		assert_eq!(violations, [(bad, HeapViolation::Overrun)]);
+
+		let violations = violations_in(|| unsafe {
+			bad = malloc(10);
+			*bad.add(12) = 0;
+			let ptr = realloc(bad, 20);
+			assert!(!ptr.is_null());
+			free(ptr);
+		});
+		assert_eq!(violations, [(bad, HeapViolation::Overrun)]);
+	}
+
+	#[test]
+	#[cfg(feature = "malloc-debug")]
+	fn test_debug_underrun() {
+		let _guard = crate::test_lock();
+		let mut bad = core::ptr::null_mut();
+		let violations = violations_in(|| unsafe {
+			bad = malloc(10);
+			*bad.sub(1) = 0;
+			free(bad);
+			// The block is leaked, so a realloc is also refused
+			assert!(realloc(bad, 20).is_null());
+		});
+		assert_eq!(
+			violations,
+			[
+				(bad, HeapViolation::Underrun),
+				(bad, HeapViolation::Underrun)
+			]
+		);
+	}
+
+	// With our own heap, another test could reuse the block between the two
+	// frees.
+	#[test]
+	#[cfg(all(feature = "malloc-debug", not(feature = "heap")))]
+	fn test_debug_double_free() {
+		let _guard = crate::test_lock();
+		let mut bad = core::ptr::null_mut();
+		let violations = violations_in(|| unsafe {
+			bad = malloc(10);
+			free(bad);
+			free(bad);
+		});
+		assert_eq!(violations, [(bad, HeapViolation::DoubleFree)]);
+	}
+
+	std::thread_local! {
+		static OOM_CALLS: core::cell::RefCell<Vec<usize>> = const { core::cell::RefCell::new(Vec::new()) };
+	}
+
+	/// A size we can express but never allocate
+	const HUGE: usize = isize::MAX as usize / 2;
+
+	extern "C" fn retry_twice(size: usize) -> c_int {
+		OOM_CALLS.with(|calls| {
+			calls.borrow_mut().push(size);
+			(calls.borrow().len() < 3) as c_int
+		})
+	}
+
+	fn give_up(size: usize) -> bool {
+		OOM_CALLS.with(|calls| calls.borrow_mut().push(size));
+		false
+	}
+
+	#[test]
+	fn test_oom_handler_retries() {
+		let _guard = crate::test_lock();
+		assert!(malloc_oom::tinyrlibc_set_oom_handler(Some(retry_twice)).is_none());
+		set_errno(0);
+		assert!(unsafe { malloc(HUGE) }.is_null());
+		assert_eq!(errno(), ENOMEM);
+		assert_eq!(OOM_CALLS.with(|calls| calls.take()), [HUGE; 3]);
+
+		let ptr = unsafe { malloc(10) };
+		assert!(unsafe { realloc(ptr, HUGE) }.is_null());
+		assert_eq!(OOM_CALLS.with(|calls| calls.take()), [HUGE; 3]);
+		unsafe { free(ptr) };
+
+		assert!(malloc_oom::tinyrlibc_set_oom_handler(None).is_some());
+		assert!(unsafe { calloc(HUGE, 1) }.is_null());
+		assert_eq!(OOM_CALLS.with(|calls| calls.take()), []);
+	}
+
+	#[test]
+	fn test_oom_handler_rust() {
+		let _guard = crate::test_lock();
+		assert!(malloc_oom::set_oom_handler(Some(give_up)).is_none());
+		assert!(unsafe { calloc(HUGE, 1) }.is_null());
+		// Impossible sizes don't call the handler
+		assert!(unsafe { malloc(usize::MAX) }.is_null());
+		assert_eq!(OOM_CALLS.with(|calls| calls.take()), [HUGE]);
+
+		// Setting a C handler replaces the Rust one
+		malloc_oom::tinyrlibc_set_oom_handler(Some(retry_twice));
+		assert!(malloc_oom::set_oom_handler(None).is_none());
+		assert!(unsafe { malloc(HUGE) }.is_null());
+		assert_eq!(OOM_CALLS.with(|calls| calls.take()), []);
+	}
+
+	#[test]
+	#[cfg(feature = "malloc-leaks")]
+	fn test_leak_tracking() {
+		let _guard = crate::test_lock();
+		let checkpoint = malloc_leaks::leak_checkpoint();
+		let kept = unsafe { malloc(10) };
+		let freed = unsafe { calloc(2, 10) };
+		let moved = unsafe { aligned_alloc(64, 30) };
+		let moved = unsafe { realloc(moved, 3000) };
+		unsafe { free(freed) };
+
+		let mut leaks = Vec::new();
+		let count =
+			malloc_leaks::leaks_since(&checkpoint, |entry| leaks.push((entry.ptr, entry.size)));
+		assert_eq!(count, 2);
+		assert_eq!(leaks, [(kept, 10), (moved, 3000)]);
+
+		unsafe {
+			free(kept);
+			free(moved);
+		}
+		malloc_leaks::assert_no_leaks_since(&checkpoint);
+	}
+
+	#[test]
+	#[cfg(feature = "malloc-pools")]
+	fn test_pools() {
+		let _guard = crate::test_lock();
+		use crate::malloc_pools::Pool;
+		static POOL: Pool<32, 4> = Pool::new();
+		assert!(POOL.register());
+
+		let small = unsafe { malloc(32) };
+		let zeroed = unsafe { calloc(3, 10) };
+		let large = unsafe { malloc(33) };
+		let aligned = unsafe { aligned_alloc(64, 8) };
+		assert!(POOL.contains(small));
+		assert!(POOL.contains(zeroed));
+		assert!(!POOL.contains(large));
+		assert!(!POOL.contains(aligned));
+		assert!((0..30).all(|i| unsafe { *zeroed.add(i) } == 0));
+		assert_eq!(unsafe { malloc_usable_size(small) }, 32);
+
+		// Shrinking stays in the pool, and growing moves out of it
+		unsafe { core::ptr::write_bytes(small, 0x11, 32) };
+		let small = unsafe { realloc(small, 8) };
+		assert!(POOL.contains(small));
+		let grown = unsafe { realloc(small, 100) };
+		assert!(!POOL.contains(grown));
+		assert!((0..8).all(|i| unsafe { *grown.add(i) } == 0x11));
+
+		// Once the pool is empty, the general allocator takes over
+		let blocks: Vec<_> = (0..4).map(|_| unsafe { malloc(16) }).collect();
+		assert_eq!(blocks.iter().filter(|&&ptr| POOL.contains(ptr)).count(), 3);
+		unsafe {
+			for ptr in blocks {
+				free(ptr);
+			}
+			free(zeroed);
+			free(large);
+			free(aligned);
+			free(grown);
+		}
+		// All four blocks are free again
+		let blocks: Vec<_> = (0..4).map(|_| unsafe { malloc(1) }).collect();
+		assert!(blocks.iter().all(|&ptr| POOL.contains(ptr)));
+		for ptr in blocks {
+			unsafe { free(ptr) };
+		}
+	}
+
+	#[test]
+	fn test_posix_memalign_too_large() {
+		let _guard = crate::test_lock();
+		let mut ptr = core::ptr::null_mut();
+		set_errno(0);
+		assert_eq!(unsafe { posix_memalign(&mut ptr, 64, usize::MAX) }, ENOMEM);
+		assert!(ptr.is_null());
+		assert_eq!(errno(), 0);
+	}
 }
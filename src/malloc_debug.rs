@@ -0,0 +1,146 @@
+//! Checks for heap misuse, used by `malloc.rs` with the `malloc-debug` feature.
+//!
+//! Every block gets a canary word just before it and guard bytes just after
+//! it. Fresh memory is filled with [`FRESH_BYTE`] and freed memory with
+//! [`FREED_BYTE`], so that reads of uninitialised or freed memory stand out.
+//! Problems are reported to the handler set with
+//! [`tinyrlibc_set_heap_violation_handler`], which by default panics.
+//!
+//! Licensed under the Blue Oak Model Licence 1.0.0
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// The byte `malloc` fills new memory with
+pub const FRESH_BYTE: u8 = 0xCD;
+
+/// The byte `free` fills memory with
+pub const FREED_BYTE: u8 = 0xDD;
+
+/// The byte the guard after each block is filled with
+pub const GUARD_BYTE: u8 = 0xFD;
+
+/// The number of guard bytes after each block
+pub(crate) const GUARD_SIZE: usize = 2 * core::mem::size_of::<usize>();
+
+/// The canary before a block that is in use
+pub(crate) const LIVE_CANARY: usize = 0xA110_CA7E_A110_CA7E_u64 as usize;
+
+/// The canary before a block that has been freed
+pub(crate) const FREED_CANARY: usize = 0xDEAD_B10C_DEAD_B10C_u64 as usize;
+
+/// A problem found with a heap block.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapViolation {
+	/// The block was freed twice (or realloc'd after being freed)
+	DoubleFree = 1,
+	/// The canary before the block was overwritten, or the pointer didn't come
+	/// from `malloc`. The block is leaked, as its header can't be trusted.
+	Underrun = 2,
+	/// The guard bytes after the block were overwritten
+	Overrun = 3,
+}
+
+/// A function to call when a heap violation is found.
+///
+/// It is given the pointer passed to `free` or `realloc`. If it returns, a
+/// double-freed or underrun block is left alone, and an overrun block is
+/// freed (or reallocated) as normal.
+pub type HeapViolationHandler = unsafe extern "C" fn(ptr: *mut u8, violation: HeapViolation);
+
+/// The registered handler, or 0 for the default
+static HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+/// Set the function to call when a heap violation is found, returning the
+/// previous one.
+///
+/// Passing `None` restores the default, which panics.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn tinyrlibc_set_heap_violation_handler(
+	handler: Option<HeapViolationHandler>,
+) -> Option<HeapViolationHandler> {
+	let new = handler.map_or(0, |handler| handler as usize);
+	let old = crate::hook::swap(&HANDLER, new);
+	if old == 0 {
+		None
+	} else {
+		// Safety: we only ever store valid handlers (or zero)
+		Some(unsafe { core::mem::transmute::<usize, HeapViolationHandler>(old) })
+	}
+}
+
+/// Report a heap violation to the registered handler.
+pub(crate) fn report(ptr: *mut u8, violation: HeapViolation) {
+	let handler = HANDLER.load(Ordering::Acquire);
+	if handler == 0 {
+		panic!("heap violation at {:p}: {:?}", ptr, violation);
+	}
+	unsafe {
+		let handler: HeapViolationHandler = core::mem::transmute(handler);
+		handler(ptr, violation);
+	}
+}
+
+/// Check the canary before a block.
+pub(crate) fn check_canary(canary: usize) -> Result<(), HeapViolation> {
+	match canary {
+		LIVE_CANARY => Ok(()),
+		FREED_CANARY => Err(HeapViolation::DoubleFree),
+		_ => Err(HeapViolation::Underrun),
+	}
+}
+
+/// Fill the guard bytes after a block of `size` bytes.
+///
+/// # Safety
+///
+/// There must be `GUARD_SIZE` writable bytes at `ptr + size`.
+pub(crate) unsafe fn write_guard(ptr: *mut u8, size: usize) {
+	core::ptr::write_bytes(ptr.add(size), GUARD_BYTE, GUARD_SIZE);
+}
+
+/// Check the guard bytes after a block of `size` bytes.
+///
+/// # Safety
+///
+/// There must be `GUARD_SIZE` readable bytes at `ptr + size`.
+pub(crate) unsafe fn check_guard(ptr: *mut u8, size: usize) -> Result<(), HeapViolation> {
+	let guard = core::slice::from_raw_parts(ptr.add(size), GUARD_SIZE);
+	if guard.iter().all(|&byte| byte == GUARD_BYTE) {
+		Ok(())
+	} else {
+		Err(HeapViolation::Overrun)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn canary() {
+		assert_eq!(check_canary(LIVE_CANARY), Ok(()));
+		assert_eq!(check_canary(FREED_CANARY), Err(HeapViolation::DoubleFree));
+		assert_eq!(check_canary(0), Err(HeapViolation::Underrun));
+	}
+
+	#[test]
+	fn guard() {
+		let mut buffer = [0u8; 4 + GUARD_SIZE];
+		unsafe { write_guard(buffer.as_mut_ptr(), 4) };
+		assert_eq!(unsafe { check_guard(buffer.as_mut_ptr(), 4) }, Ok(()));
+		buffer[4 + GUARD_SIZE - 1] = 0;
+		assert_eq!(
+			unsafe { check_guard(buffer.as_mut_ptr(), 4) },
+			Err(HeapViolation::Overrun)
+		);
+	}
+
+	// `free` can't unwind, so the default handler is tested directly
+	#[test]
+	#[should_panic(expected = "DoubleFree")]
+	fn default_handler_panics() {
+		let _guard = crate::test_lock();
+		report(core::ptr::null_mut(), HeapViolation::DoubleFree);
+	}
+}
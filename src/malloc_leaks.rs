@@ -0,0 +1,273 @@
+//! Leak tracking, used by `malloc.rs` with the `malloc-leaks` feature.
+//!
+//! Every live allocation is recorded in a fixed-size table, along with a
+//! serial number that increases with each allocation. Taking a
+//! [`LeakCheckpoint`] notes the current serial number, so any allocation
+//! still live with a later serial number was made since the checkpoint.
+//!
+//! This is meant for tests on a host, so it favours simplicity over speed:
+//! `free` searches the whole table.
+//!
+//! Licensed under the Blue Oak Model Licence 1.0.0
+
+use core::ffi::c_void;
+
+use crate::spinlock::SpinLock;
+
+/// The most live allocations we can track at once.
+///
+/// Allocations beyond this are counted, but not tracked.
+pub const LEAK_TABLE_SIZE: usize = 1024;
+
+/// A live allocation.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LiveAllocation {
+	/// The pointer returned to the caller (NULL if this entry is unused)
+	pub ptr: *mut u8,
+	/// The number of bytes asked for
+	pub size: usize,
+	/// The address given to [`tinyrlibc_leak_set_caller`] before the
+	/// allocation, or NULL
+	pub caller: *const c_void,
+	/// The serial number of the allocation, which increases with each one
+	pub serial: usize,
+}
+
+impl LiveAllocation {
+	const EMPTY: LiveAllocation = LiveAllocation {
+		ptr: core::ptr::null_mut(),
+		size: 0,
+		caller: core::ptr::null(),
+		serial: 0,
+	};
+}
+
+/// A point in time to check for leaks from.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LeakCheckpoint {
+	/// The serial number of the last allocation before the checkpoint
+	pub serial: usize,
+	/// The number of allocations which weren't tracked before the checkpoint
+	pub untracked: usize,
+}
+
+/// The table of live allocations.
+struct Table {
+	entries: [LiveAllocation; LEAK_TABLE_SIZE],
+	/// The serial number of the last allocation
+	serial: usize,
+	/// The number of allocations we had no room to track
+	untracked: usize,
+	/// The caller to record against the next allocation
+	next_caller: *const c_void,
+}
+
+impl Table {
+	const EMPTY: Table = Table {
+		entries: [LiveAllocation::EMPTY; LEAK_TABLE_SIZE],
+		serial: 0,
+		untracked: 0,
+		next_caller: core::ptr::null(),
+	};
+}
+
+/// The live allocations
+static TABLE: SpinLock<Table> = SpinLock::new(Table::EMPTY);
+
+/// Run `f` with the table locked.
+///
+/// `f` must not allocate, or it will deadlock.
+fn with_table<R>(f: impl FnOnce(&mut Table) -> R) -> R {
+	TABLE.with(f)
+}
+
+/// Forget every allocation, between tests.
+#[cfg(test)]
+pub(crate) fn reset() {
+	with_table(|table| *table = Table::EMPTY);
+}
+
+/// Record a new allocation.
+pub(crate) fn record(ptr: *mut u8, size: usize) {
+	with_table(|table| {
+		table.serial += 1;
+		let caller = core::mem::replace(&mut table.next_caller, core::ptr::null());
+		let entry = LiveAllocation {
+			ptr,
+			size,
+			caller,
+			serial: table.serial,
+		};
+		match table.entries.iter_mut().find(|entry| entry.ptr.is_null()) {
+			Some(slot) => *slot = entry,
+			None => table.untracked += 1,
+		}
+	});
+}
+
+/// Record that an allocation has moved or changed size.
+///
+/// It keeps its serial number, so it doesn't count as a new allocation.
+pub(crate) fn update(old_ptr: *mut u8, new_ptr: *mut u8, size: usize) {
+	with_table(|table| {
+		if let Some(entry) = table.entries.iter_mut().find(|entry| entry.ptr == old_ptr) {
+			entry.ptr = new_ptr;
+			entry.size = size;
+		}
+	});
+}
+
+/// Record that an allocation has been freed.
+pub(crate) fn remove(ptr: *mut u8) {
+	with_table(|table| {
+		if let Some(entry) = table.entries.iter_mut().find(|entry| entry.ptr == ptr) {
+			*entry = LiveAllocation::EMPTY;
+		}
+	});
+}
+
+/// Take a checkpoint, to check for leaks from.
+pub fn leak_checkpoint() -> LeakCheckpoint {
+	with_table(|table| LeakCheckpoint {
+		serial: table.serial,
+		untracked: table.untracked,
+	})
+}
+
+/// Call `f` with each allocation made since `checkpoint` which is still live,
+/// oldest first, returning how many there were.
+///
+/// Allocations which weren't tracked (because the table was full) are always
+/// counted, even if they have since been freed, but `f` isn't called for
+/// them. `f` is called with the table locked, so it must not allocate.
+pub fn leaks_since(checkpoint: &LeakCheckpoint, mut f: impl FnMut(&LiveAllocation)) -> usize {
+	with_table(|table| {
+		let mut count = table.untracked - checkpoint.untracked;
+		let mut serial = checkpoint.serial;
+		// The table isn't in order, so find the next oldest each time round
+		while let Some(entry) = table
+			.entries
+			.iter()
+			.filter(|entry| !entry.ptr.is_null() && entry.serial > serial)
+			.min_by_key(|entry| entry.serial)
+		{
+			f(entry);
+			count += 1;
+			serial = entry.serial;
+		}
+		count
+	})
+}
+
+/// Panic if any allocation made since `checkpoint` is still live.
+pub fn assert_no_leaks_since(checkpoint: &LeakCheckpoint) {
+	let mut first = None;
+	let count = leaks_since(checkpoint, |entry| {
+		first.get_or_insert(*entry);
+	});
+	match first {
+		_ if count == 0 => {}
+		Some(first) => panic!(
+			"{} allocation(s) leaked since checkpoint, the first being {} bytes at {:p} (caller {:p})",
+			count, first.size, first.ptr, first.caller
+		),
+		None => panic!("{} untracked allocation(s) leaked since checkpoint", count),
+	}
+}
+
+/// Set the caller address to record against the next allocation.
+///
+/// For example, a C wrapper might pass `__builtin_return_address(0)`.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn tinyrlibc_leak_set_caller(caller: *const c_void) {
+	with_table(|table| table.next_caller = caller);
+}
+
+/// C interface to [`leak_checkpoint`].
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn tinyrlibc_leak_checkpoint() -> LeakCheckpoint {
+	leak_checkpoint()
+}
+
+/// C interface to [`leaks_since`].
+///
+/// Copies up to `max` of the allocations made since `checkpoint` which are
+/// still live into `leaks`, oldest first, and returns how many there were in
+/// total.
+///
+/// # Safety
+///
+/// `checkpoint` must be valid, and `leaks` must be valid for writing `max`
+/// entries (or NULL, if `max` is 0).
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn tinyrlibc_leaks_since(
+	checkpoint: *const LeakCheckpoint,
+	leaks: *mut LiveAllocation,
+	max: usize,
+) -> usize {
+	let mut copied = 0;
+	leaks_since(&*checkpoint, |entry| {
+		if copied < max {
+			leaks.add(copied).write(*entry);
+			copied += 1;
+		}
+	})
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn tracks_live_allocations() {
+		let _guard = crate::test_lock();
+		let checkpoint = leak_checkpoint();
+		record(0x1000 as *mut u8, 10);
+		tinyrlibc_leak_set_caller(0x42 as *const c_void);
+		record(0x2000 as *mut u8, 20);
+		record(0x3000 as *mut u8, 30);
+		remove(0x1000 as *mut u8);
+		update(0x3000 as *mut u8, 0x4000 as *mut u8, 40);
+
+		let mut leaks = [LiveAllocation::EMPTY; 4];
+		let count = unsafe { tinyrlibc_leaks_since(&checkpoint, leaks.as_mut_ptr(), 4) };
+		assert_eq!(count, 2);
+		assert_eq!(leaks[0].ptr, 0x2000 as *mut u8);
+		assert_eq!(leaks[0].caller, 0x42 as *const c_void);
+		assert_eq!(leaks[1].ptr, 0x4000 as *mut u8);
+		assert_eq!(leaks[1].size, 40);
+		assert!(leaks[1].caller.is_null());
+		assert!(leaks[0].serial < leaks[1].serial);
+
+		let later = leak_checkpoint();
+		assert_no_leaks_since(&later);
+		remove(0x2000 as *mut u8);
+		remove(0x4000 as *mut u8);
+		assert_no_leaks_since(&checkpoint);
+	}
+
+	#[test]
+	fn counts_untracked() {
+		let _guard = crate::test_lock();
+		let checkpoint = leak_checkpoint();
+		for idx in 1..=LEAK_TABLE_SIZE + 2 {
+			record(idx as *mut u8, 1);
+		}
+		assert_eq!(leaks_since(&checkpoint, |_| {}), LEAK_TABLE_SIZE + 2);
+		for idx in 1..=LEAK_TABLE_SIZE {
+			remove(idx as *mut u8);
+		}
+		assert_eq!(leaks_since(&checkpoint, |_| {}), 2);
+	}
+
+	#[test]
+	#[should_panic(expected = "1 allocation(s) leaked")]
+	fn assert_fails() {
+		let _guard = crate::test_lock();
+		let checkpoint = leak_checkpoint();
+		record(0x1000 as *mut u8, 10);
+		assert_no_leaks_since(&checkpoint);
+	}
+}
@@ -0,0 +1,69 @@
+//! The out-of-memory handler, used by `malloc.rs`.
+//!
+//! This works like C++'s `std::set_new_handler`: when an allocation fails,
+//! the handler is called with the number of bytes asked for. It can free some
+//! memory (such as a cache) and ask for the allocation to be tried again, or
+//! give up, in which case the allocation returns NULL.
+//!
+//! Licensed under the Blue Oak Model Licence 1.0.0
+
+use core::ffi::c_int;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// A C out-of-memory handler.
+///
+/// Returns non-zero if the allocation should be tried again.
+pub type OomHandler = unsafe extern "C" fn(size: usize) -> c_int;
+
+/// A Rust out-of-memory handler.
+///
+/// Returns `true` if the allocation should be tried again.
+pub type RustOomHandler = fn(size: usize) -> bool;
+
+/// The registered C handler, or 0 for none
+static C_HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+/// The registered Rust handler, or 0 for none
+static RUST_HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+/// Set the function to call when `malloc`, `calloc` or `realloc` runs out of
+/// memory, returning the previous one.
+///
+/// This replaces any handler set with [`set_oom_handler`]. Passing NULL
+/// removes the handler, so allocations fail straight away.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn tinyrlibc_set_oom_handler(handler: Option<OomHandler>) -> Option<OomHandler> {
+	let old = crate::hook::swap(&C_HANDLER, handler.map_or(0, |handler| handler as usize));
+	RUST_HANDLER.store(0, Ordering::Release);
+	// Safety: we only ever store valid handlers (or zero)
+	(old != 0).then(|| unsafe { core::mem::transmute::<usize, OomHandler>(old) })
+}
+
+/// Set the function to call when `malloc`, `calloc` or `realloc` runs out of
+/// memory, returning the previous one.
+///
+/// This replaces any handler set with [`tinyrlibc_set_oom_handler`]. Passing
+/// `None` removes the handler, so allocations fail straight away.
+pub fn set_oom_handler(handler: Option<RustOomHandler>) -> Option<RustOomHandler> {
+	let old = crate::hook::swap(&RUST_HANDLER, handler.map_or(0, |handler| handler as usize));
+	C_HANDLER.store(0, Ordering::Release);
+	// Safety: we only ever store valid handlers (or zero)
+	(old != 0).then(|| unsafe { core::mem::transmute::<usize, RustOomHandler>(old) })
+}
+
+/// Tell the handler that an allocation of `size` bytes failed.
+///
+/// Returns `true` if the allocation should be tried again.
+pub(crate) fn should_retry(size: usize) -> bool {
+	let c = C_HANDLER.load(Ordering::Acquire);
+	let rust = RUST_HANDLER.load(Ordering::Acquire);
+	if c != 0 {
+		let handler: OomHandler = unsafe { core::mem::transmute(c) };
+		unsafe { handler(size) != 0 }
+	} else if rust != 0 {
+		let handler: RustOomHandler = unsafe { core::mem::transmute(rust) };
+		handler(size)
+	} else {
+		false
+	}
+}
@@ -0,0 +1,317 @@
+//! Fixed-size pools for small allocations, used by `malloc.rs` with the
+//! `malloc-pools` feature.
+//!
+//! Each pool is a run of equal-sized blocks, with the free ones kept in a
+//! list. `malloc` and `calloc` take a block from the smallest pool the
+//! allocation fits in (moving on to larger pools if that one is empty), and
+//! only go to the general allocator if no pool can help. Pool blocks have the
+//! same header as any other allocation, and `free` and `realloc` tell them
+//! apart by their address.
+//!
+//! ```rust,ignore
+//! static SMALL: tinyrlibc::Pool<16, 256> = tinyrlibc::Pool::new();
+//! static MEDIUM: tinyrlibc::Pool<64, 128> = tinyrlibc::Pool::new();
+//!
+//! SMALL.register();
+//! MEDIUM.register();
+//! ```
+//!
+//! Licensed under the Blue Oak Model Licence 1.0.0
+
+use core::cell::UnsafeCell;
+use core::ffi::{c_int, c_void};
+use core::mem::MaybeUninit;
+use core::ptr::null_mut;
+
+use crate::malloc::{MAX_ALIGN, MIN_OFFSET, TAIL};
+use crate::spinlock::SpinLock;
+
+/// The most pools which can be registered.
+pub const MAX_POOLS: usize = 8;
+
+/// One block in a [`Pool`], with room for the `malloc` header before the data
+/// and any guard bytes after it.
+#[repr(C, align(16))]
+struct Block<const SIZE: usize> {
+	header: [u8; MIN_OFFSET],
+	data: [u8; SIZE],
+	tail: [u8; TAIL],
+}
+
+/// A pool of `COUNT` blocks, for allocations of up to `SIZE` bytes.
+///
+/// Declare it as a `static`, and call [`Pool::register`] before the first
+/// allocation it should serve.
+pub struct Pool<const SIZE: usize, const COUNT: usize> {
+	blocks: UnsafeCell<MaybeUninit<[Block<SIZE>; COUNT]>>,
+}
+
+// Safety: the blocks are only accessed through the pool registry, which is
+// locked.
+unsafe impl<const SIZE: usize, const COUNT: usize> Sync for Pool<SIZE, COUNT> {}
+
+impl<const SIZE: usize, const COUNT: usize> Pool<SIZE, COUNT> {
+	/// Create a pool.
+	#[allow(clippy::new_without_default)]
+	pub const fn new() -> Pool<SIZE, COUNT> {
+		Pool {
+			blocks: UnsafeCell::new(MaybeUninit::zeroed()),
+		}
+	}
+
+	/// Start using this pool for allocations.
+	///
+	/// Returns `false` if the pool was already registered, or [`MAX_POOLS`]
+	/// pools have been registered.
+	pub fn register(&'static self) -> bool {
+		let start = self.blocks.get().cast::<u8>();
+		let stride = core::mem::size_of::<Block<SIZE>>();
+		// Safety: the blocks are ours, and are only handed over once
+		unsafe { register_region(start, stride, COUNT, SIZE) }
+	}
+
+	/// Check whether `ptr` points into this pool.
+	pub fn contains(&self, ptr: *const u8) -> bool {
+		let start = self.blocks.get() as usize;
+		let end = start + core::mem::size_of::<[Block<SIZE>; COUNT]>();
+		(start..end).contains(&(ptr as usize))
+	}
+}
+
+/// A free block, holding the next free block in the same pool.
+struct FreeBlock {
+	next: *mut FreeBlock,
+}
+
+/// A registered pool.
+#[derive(Clone, Copy)]
+struct Region {
+	start: *mut u8,
+	end: *mut u8,
+	/// The size of each block, including the `malloc` header
+	stride: usize,
+	free: *mut FreeBlock,
+}
+
+/// The registered pools, smallest first.
+struct Registry {
+	regions: [Option<Region>; MAX_POOLS],
+}
+
+impl Registry {
+	/// Find the pool containing `base`.
+	fn find(&mut self, base: *mut u8) -> Option<&mut Region> {
+		self.regions
+			.iter_mut()
+			.flatten()
+			.find(|region| region.start <= base && base < region.end)
+	}
+}
+
+/// The registered pools
+static REGISTRY: SpinLock<Registry> = SpinLock::new(Registry {
+	regions: [None; MAX_POOLS],
+});
+
+/// Run `f` with the registry locked.
+fn with_registry<R>(f: impl FnOnce(&mut Registry) -> R) -> R {
+	REGISTRY.with(f)
+}
+
+/// Forget every registered pool, between tests.
+#[cfg(test)]
+pub(crate) fn reset() {
+	with_registry(|registry| registry.regions = [None; MAX_POOLS]);
+}
+
+/// Add `count` blocks of `stride` bytes, starting at `start`, as a pool for
+/// allocations of up to `size` bytes.
+///
+/// Returns `false` if the memory is already in a pool, or [`MAX_POOLS`] pools
+/// have been registered.
+///
+/// # Safety
+///
+/// The memory must be valid for reads and writes, aligned to `MAX_ALIGN`,
+/// and not used for anything else.
+unsafe fn register_region(start: *mut u8, stride: usize, count: usize, size: usize) -> bool {
+	debug_assert!(stride >= MIN_OFFSET + size + TAIL && stride % MAX_ALIGN == 0);
+	if count == 0 {
+		return true;
+	}
+	with_registry(|registry| {
+		if registry.find(start).is_some() {
+			return false;
+		}
+		let Some(slot) = registry.regions.iter().position(Option::is_none) else {
+			return false;
+		};
+		// Thread the free list through the blocks, first block first
+		let mut free = null_mut();
+		for idx in (0..count).rev() {
+			let block = start.add(idx * stride).cast::<FreeBlock>();
+			block.write(FreeBlock { next: free });
+			free = block;
+		}
+		registry.regions[slot] = Some(Region {
+			start,
+			end: start.add(count * stride),
+			stride,
+			free,
+		});
+		registry.regions[..=slot].sort_unstable_by_key(|region| region.map(|region| region.stride));
+		true
+	})
+}
+
+/// Add a pool in `len` bytes of memory at `memory`, for allocations of up to
+/// `size` bytes, returning the number of blocks in it.
+///
+/// Returns 0 if the memory is too small for even one block, is already in a
+/// pool, or [`MAX_POOLS`] pools have been registered.
+///
+/// # Safety
+///
+/// The memory must be valid for reads and writes, and not used for anything
+/// else.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn tinyrlibc_pool_register(
+	memory: *mut c_void,
+	len: usize,
+	size: usize,
+) -> c_int {
+	let Some(stride) = size
+		.checked_add(MIN_OFFSET + TAIL)
+		.and_then(|stride| stride.checked_next_multiple_of(MAX_ALIGN))
+	else {
+		return 0;
+	};
+	let memory = memory.cast::<u8>();
+	let skip = memory.align_offset(MAX_ALIGN);
+	let count = len.saturating_sub(skip) / stride;
+	let Ok(result) = c_int::try_from(count) else {
+		return 0;
+	};
+	if count == 0 || !register_region(memory.add(skip), stride, count, size) {
+		return 0;
+	}
+	result
+}
+
+/// Take a block of at least `size` bytes from a pool, returning NULL if none
+/// is free.
+pub(crate) fn alloc(size: usize) -> *mut u8 {
+	with_registry(|registry| {
+		for region in registry.regions.iter_mut().flatten() {
+			if region.stride >= size && !region.free.is_null() {
+				let block = region.free;
+				// Safety: everything on the free list is a free block
+				region.free = unsafe { (*block).next };
+				return block.cast();
+			}
+		}
+		null_mut()
+	})
+}
+
+/// Get the size of the pool block at `base`, or `None` if `base` isn't in a
+/// pool.
+pub(crate) fn capacity(base: *mut u8) -> Option<usize> {
+	with_registry(|registry| registry.find(base).map(|region| region.stride))
+}
+
+/// Return the block at `base` to its pool, or return `false` if `base` isn't
+/// in a pool.
+///
+/// # Safety
+///
+/// If `base` is in a pool, it must be an allocated block.
+pub(crate) unsafe fn dealloc(base: *mut u8) -> bool {
+	with_registry(|registry| {
+		let Some(region) = registry.find(base) else {
+			return false;
+		};
+		let block = base.cast::<FreeBlock>();
+		block.write(FreeBlock { next: region.free });
+		region.free = block;
+		true
+	})
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn takes_smallest_block() {
+		let _guard = crate::test_lock();
+		static SMALL: Pool<16, 2> = Pool::new();
+		static LARGE: Pool<64, 1> = Pool::new();
+		assert!(LARGE.register());
+		assert!(SMALL.register());
+		assert!(!SMALL.register());
+
+		let a = alloc(MIN_OFFSET + 10);
+		let b = alloc(MIN_OFFSET + 16);
+		assert!(SMALL.contains(a) && SMALL.contains(b));
+		assert_ne!(a, b);
+		// The small pool is empty, so this comes from the large one
+		let c = alloc(MIN_OFFSET + 1);
+		assert!(LARGE.contains(c));
+		assert!(alloc(MIN_OFFSET + 1).is_null());
+		assert!(alloc(MIN_OFFSET + 65 + TAIL).is_null());
+
+		assert_eq!(capacity(a), Some(core::mem::size_of::<Block<16>>()));
+		assert_eq!(capacity(c), Some(core::mem::size_of::<Block<64>>()));
+		assert_eq!(capacity(&mut 0u8), None);
+
+		unsafe {
+			assert!(dealloc(b));
+			assert!(dealloc(c));
+			assert!(!dealloc(16 as *mut u8));
+		}
+		assert_eq!(alloc(MIN_OFFSET + 1), b);
+	}
+
+	#[test]
+	#[allow(unused_unsafe)]
+	fn registers_raw_memory() {
+		let _guard = crate::test_lock();
+		static mut MEMORY: [u8; 1000] = [0; 1000];
+		let memory = unsafe { core::ptr::addr_of_mut!(MEMORY) }.cast::<c_void>();
+		let stride = (MIN_OFFSET + 24 + TAIL).next_multiple_of(MAX_ALIGN);
+		let count = unsafe { tinyrlibc_pool_register(memory.add(1), 999, 24) };
+		assert!(count as usize >= 999 / stride - 1);
+		for _ in 0..count {
+			let block = alloc(MIN_OFFSET + 24 + TAIL);
+			assert!(!block.is_null());
+			assert_eq!(block as usize % MAX_ALIGN, 0);
+		}
+		assert!(alloc(MIN_OFFSET + 1).is_null());
+		assert_eq!(unsafe { tinyrlibc_pool_register(memory, 8, 24) }, 0);
+	}
+
+	#[test]
+	#[allow(unused_unsafe)]
+	fn registry_fills_up() {
+		let _guard = crate::test_lock();
+		static mut MEMORY: [u8; 4096] = [0; 4096];
+		let memory = unsafe { core::ptr::addr_of_mut!(MEMORY) }.cast::<u8>();
+		for idx in 0..MAX_POOLS {
+			let pool = unsafe { memory.add(idx * 512) }.cast();
+			assert!(unsafe { tinyrlibc_pool_register(pool, 512, 32) } > 0);
+		}
+		let pool = unsafe { memory.add(MAX_POOLS * 512 - 512) }.cast();
+		assert_eq!(unsafe { tinyrlibc_pool_register(pool, 512, 32) }, 0);
+
+		// A pool turned away while the registry is full can come back later,
+		// and its memory is left alone until then
+		static POOL: Pool<16, 4> = Pool::new();
+		assert!(!POOL.register());
+		let size = core::mem::size_of::<[Block<16>; 4]>();
+		let blocks = unsafe { core::slice::from_raw_parts(POOL.blocks.get().cast::<u8>(), size) };
+		assert!(blocks.iter().all(|&byte| byte == 0));
+		reset();
+		assert!(POOL.register());
+	}
+}
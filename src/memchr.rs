@@ -29,7 +29,7 @@ mod test {
 	fn normal() {
 		let s = b"hello world";
 		assert_eq!(
-			unsafe { memchr(s.as_ptr() as *const c_void, b'w' as c_int, s.len() as usize) },
+			unsafe { memchr(s.as_ptr() as *const c_void, b'w' as c_int, s.len()) },
 			unsafe { s.as_ptr().offset(6) } as *const c_void
 		);
 	}
@@ -38,7 +38,7 @@ mod test {
 	fn not_found() {
 		let s = b"hello world";
 		assert_eq!(
-			unsafe { memchr(s.as_ptr() as *const c_void, b'x' as c_int, s.len() as usize) },
+			unsafe { memchr(s.as_ptr() as *const c_void, b'x' as c_int, s.len()) },
 			core::ptr::null()
 		)
 	}
@@ -0,0 +1,549 @@
+//! Rust implementation of the C library's multibyte/wide character conversion
+//! functions (`mblen`, `mbtowc`, `wctomb`, `mbrtowc`, `mbstowcs`, `wcstombs`,
+//! etc).
+//!
+//! The only supported multibyte encoding is UTF-8. Overlong encodings,
+//! surrogates and values above U+10FFFF are rejected.
+//!
+//! Licensed under the Blue Oak Model Licence 1.0.0
+
+use core::ffi::{c_char, c_int};
+
+use crate::errno::{set_errno, EILSEQ};
+use crate::wchar::wchar_t;
+
+/// The maximum number of bytes in a multibyte character, in any locale.
+pub const MB_LEN_MAX: usize = 4;
+
+/// The maximum number of bytes in a multibyte character, in the current
+/// locale.
+pub const MB_CUR_MAX: usize = 4;
+
+/// Returned by the restartable functions when the input is not valid UTF-8.
+const INVALID: usize = usize::MAX;
+
+/// Returned by the restartable functions when the input ends part way through
+/// a character.
+const INCOMPLETE: usize = usize::MAX - 1;
+
+/// The C `mbstate_t` type, which holds a partially decoded multibyte
+/// character.
+///
+/// This has the same size as the `mbstate_t` in newlib and glibc. A zeroed
+/// value is the initial state.
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct mbstate_t {
+	/// Bytes still required (bits 0..8) and total sequence length (bits 8..16)
+	count: c_int,
+	/// The code point bits decoded so far
+	value: u32,
+}
+
+/// The result of feeding one byte to the decoder.
+enum Step {
+	/// More bytes are needed
+	Incomplete,
+	/// A code point was decoded
+	Complete(u32),
+	/// The byte sequence is not valid UTF-8
+	Invalid,
+}
+
+impl mbstate_t {
+	/// Create a new `mbstate_t` in the initial state.
+	pub const fn new() -> mbstate_t {
+		mbstate_t { count: 0, value: 0 }
+	}
+
+	/// Is this in the initial state?
+	pub fn is_initial(&self) -> bool {
+		self.count == 0
+	}
+
+	/// Feed one byte of UTF-8 to the decoder.
+	///
+	/// The partially decoded value is range checked after every byte, so an
+	/// overlong encoding or surrogate is rejected as early as possible. On
+	/// error, the state is reset.
+	fn feed(&mut self, byte: u8) -> Step {
+		let (len, remaining, partial) = if self.count == 0 {
+			let len = match byte {
+				0x00..=0x7F => return Step::Complete(u32::from(byte)),
+				0xC0..=0xDF => 2,
+				0xE0..=0xEF => 3,
+				0xF0..=0xF7 => 4,
+				_ => return Step::Invalid,
+			};
+			(len, len - 1, u32::from(byte & (0x7F >> len)))
+		} else {
+			if byte & 0xC0 != 0x80 {
+				*self = mbstate_t::new();
+				return Step::Invalid;
+			}
+			let len = (self.count >> 8) as u32;
+			let remaining = (self.count & 0xFF) as u32 - 1;
+			(len, remaining, (self.value << 6) | u32::from(byte & 0x3F))
+		};
+
+		// The smallest and largest code points this sequence could produce
+		let shift = 6 * remaining;
+		let lowest = partial << shift;
+		let highest = lowest | ((1 << shift) - 1);
+		let minimum = match len {
+			2 => 0x80,
+			3 => 0x800,
+			_ => 0x10000,
+		};
+		if highest < minimum || lowest > 0x10FFFF || (lowest >= 0xD800 && highest <= 0xDFFF) {
+			*self = mbstate_t::new();
+			return Step::Invalid;
+		}
+
+		if remaining == 0 {
+			*self = mbstate_t::new();
+			Step::Complete(partial)
+		} else {
+			self.count = ((len << 8) | remaining) as c_int;
+			self.value = partial;
+			Step::Incomplete
+		}
+	}
+}
+
+/// Internal state for `mbrtowc` when called with a NULL `mbstate_t`.
+static mut MBRTOWC_STATE: mbstate_t = mbstate_t::new();
+
+/// Internal state for `mbrlen` when called with a NULL `mbstate_t`.
+static mut MBRLEN_STATE: mbstate_t = mbstate_t::new();
+
+/// Internal state for `mbsrtowcs` when called with a NULL `mbstate_t`.
+static mut MBSRTOWCS_STATE: mbstate_t = mbstate_t::new();
+
+/// Convert a code point to a `wchar_t`, if it fits.
+#[allow(clippy::unnecessary_fallible_conversions)]
+fn to_wchar(code_point: u32) -> Option<wchar_t> {
+	wchar_t::try_from(code_point).ok()
+}
+
+/// Encode a `wchar_t` as UTF-8, returning the number of bytes used.
+#[allow(clippy::unnecessary_fallible_conversions)]
+fn encode(wc: wchar_t, buffer: &mut [u8; MB_LEN_MAX]) -> Option<usize> {
+	let c = char::from_u32(u32::try_from(wc).ok()?)?;
+	Some(c.encode_utf8(buffer).len())
+}
+
+/// Rust implementation of C library function `mblen`
+#[cfg_attr(feature = "mblen", no_mangle)]
+pub unsafe extern "C" fn mblen(s: *const c_char, n: usize) -> c_int {
+	mbtowc(core::ptr::null_mut(), s, n)
+}
+
+/// Rust implementation of C library function `mbtowc`
+///
+/// UTF-8 has no shift states, so passing a NULL `s` returns 0.
+#[cfg_attr(feature = "mbtowc", no_mangle)]
+pub unsafe extern "C" fn mbtowc(pwc: *mut wchar_t, s: *const c_char, n: usize) -> c_int {
+	if s.is_null() {
+		return 0;
+	}
+	match mbrtowc(pwc, s, n, &mut mbstate_t::new()) {
+		INVALID => -1,
+		INCOMPLETE => {
+			set_errno(EILSEQ);
+			-1
+		}
+		len => len as c_int,
+	}
+}
+
+/// Rust implementation of C library function `wctomb`
+///
+/// UTF-8 has no shift states, so passing a NULL `s` returns 0.
+#[cfg_attr(feature = "wctomb", no_mangle)]
+pub unsafe extern "C" fn wctomb(s: *mut c_char, wc: wchar_t) -> c_int {
+	if s.is_null() {
+		return 0;
+	}
+	match wcrtomb(s, wc, &mut mbstate_t::new()) {
+		INVALID => -1,
+		len => len as c_int,
+	}
+}
+
+/// Rust implementation of C library function `mbrtowc`
+///
+/// Returns the number of bytes consumed, 0 if a null character was decoded,
+/// `(size_t)-2` if the `n` bytes were valid but did not complete a
+/// character, or `(size_t)-1` (setting `errno` to `EILSEQ`) if they were not
+/// valid UTF-8.
+#[cfg_attr(feature = "mbrtowc", no_mangle)]
+pub unsafe extern "C" fn mbrtowc(
+	pwc: *mut wchar_t,
+	s: *const c_char,
+	n: usize,
+	ps: *mut mbstate_t,
+) -> usize {
+	let ps = if ps.is_null() {
+		core::ptr::addr_of_mut!(MBRTOWC_STATE)
+	} else {
+		ps
+	};
+	if s.is_null() {
+		return mbrtowc(core::ptr::null_mut(), c"".as_ptr(), 1, ps);
+	}
+	for i in 0..n {
+		match (*ps).feed(*s.add(i) as u8) {
+			Step::Incomplete => {}
+			Step::Invalid => {
+				set_errno(EILSEQ);
+				return INVALID;
+			}
+			Step::Complete(code_point) => {
+				let Some(wc) = to_wchar(code_point) else {
+					set_errno(EILSEQ);
+					return INVALID;
+				};
+				if !pwc.is_null() {
+					*pwc = wc;
+				}
+				return if code_point == 0 { 0 } else { i + 1 };
+			}
+		}
+	}
+	INCOMPLETE
+}
+
+/// Rust implementation of C library function `mbrlen`
+#[cfg_attr(feature = "mbrlen", no_mangle)]
+pub unsafe extern "C" fn mbrlen(s: *const c_char, n: usize, ps: *mut mbstate_t) -> usize {
+	let ps = if ps.is_null() {
+		core::ptr::addr_of_mut!(MBRLEN_STATE)
+	} else {
+		ps
+	};
+	mbrtowc(core::ptr::null_mut(), s, n, ps)
+}
+
+/// Rust implementation of C library function `wcrtomb`
+///
+/// UTF-8 encoding is stateless, so `ps` is ignored.
+#[cfg_attr(feature = "wcrtomb", no_mangle)]
+pub unsafe extern "C" fn wcrtomb(s: *mut c_char, wc: wchar_t, _ps: *mut mbstate_t) -> usize {
+	if s.is_null() {
+		return 1;
+	}
+	let mut buffer = [0u8; MB_LEN_MAX];
+	let Some(len) = encode(wc, &mut buffer) else {
+		set_errno(EILSEQ);
+		return INVALID;
+	};
+	core::ptr::copy_nonoverlapping(buffer.as_ptr().cast(), s, len);
+	len
+}
+
+/// Rust implementation of C library function `mbsinit`
+#[cfg_attr(feature = "mbsinit", no_mangle)]
+pub unsafe extern "C" fn mbsinit(ps: *const mbstate_t) -> c_int {
+	(ps.is_null() || (*ps).is_initial()) as c_int
+}
+
+/// Rust implementation of C library function `mbsrtowcs`
+///
+/// If `dst` is NULL, `len` is ignored and `*src` is not updated.
+#[cfg_attr(feature = "mbsrtowcs", no_mangle)]
+pub unsafe extern "C" fn mbsrtowcs(
+	dst: *mut wchar_t,
+	src: *mut *const c_char,
+	len: usize,
+	ps: *mut mbstate_t,
+) -> usize {
+	let ps = if ps.is_null() {
+		core::ptr::addr_of_mut!(MBSRTOWCS_STATE)
+	} else {
+		ps
+	};
+	let mut s = *src;
+	let mut count = 0;
+	while dst.is_null() || count < len {
+		let mut wc: wchar_t = 0;
+		// A null byte never continues a sequence, so we can't read past the
+		// end of the string.
+		match mbrtowc(&mut wc, s, MB_CUR_MAX, ps) {
+			INVALID | INCOMPLETE => {
+				if !dst.is_null() {
+					*src = s;
+				}
+				return INVALID;
+			}
+			0 => {
+				if !dst.is_null() {
+					*dst.add(count) = 0;
+					*src = core::ptr::null();
+				}
+				return count;
+			}
+			used => {
+				if !dst.is_null() {
+					*dst.add(count) = wc;
+				}
+				s = s.add(used);
+				count += 1;
+			}
+		}
+	}
+	*src = s;
+	count
+}
+
+/// Rust implementation of C library function `wcsrtombs`
+///
+/// If `dst` is NULL, `len` is ignored and `*src` is not updated. UTF-8
+/// encoding is stateless, so `ps` is ignored.
+#[cfg_attr(feature = "wcsrtombs", no_mangle)]
+pub unsafe extern "C" fn wcsrtombs(
+	dst: *mut c_char,
+	src: *mut *const wchar_t,
+	len: usize,
+	_ps: *mut mbstate_t,
+) -> usize {
+	let mut s = *src;
+	let mut count = 0;
+	loop {
+		let mut buffer = [0u8; MB_LEN_MAX];
+		let Some(used) = encode(*s, &mut buffer) else {
+			if !dst.is_null() {
+				*src = s;
+			}
+			set_errno(EILSEQ);
+			return INVALID;
+		};
+		if !dst.is_null() {
+			if count + used > len {
+				*src = s;
+				return count;
+			}
+			core::ptr::copy_nonoverlapping(buffer.as_ptr().cast(), dst.add(count), used);
+		}
+		if *s == 0 {
+			if !dst.is_null() {
+				*src = core::ptr::null();
+			}
+			return count;
+		}
+		count += used;
+		s = s.add(1);
+	}
+}
+
+/// Rust implementation of C library function `mbstowcs`
+#[cfg_attr(feature = "mbstowcs", no_mangle)]
+pub unsafe extern "C" fn mbstowcs(dst: *mut wchar_t, src: *const c_char, len: usize) -> usize {
+	let mut src = src;
+	mbsrtowcs(dst, &mut src, len, &mut mbstate_t::new())
+}
+
+/// Rust implementation of C library function `wcstombs`
+#[cfg_attr(feature = "wcstombs", no_mangle)]
+pub unsafe extern "C" fn wcstombs(dst: *mut c_char, src: *const wchar_t, len: usize) -> usize {
+	let mut src = src;
+	wcsrtombs(dst, &mut src, len, &mut mbstate_t::new())
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::errno::errno;
+
+	/// Decode a complete byte sequence with `mbtowc`
+	fn decode(bytes: &[u8]) -> Option<wchar_t> {
+		let mut wc: wchar_t = 0;
+		let result = unsafe { mbtowc(&mut wc, bytes.as_ptr().cast(), bytes.len()) };
+		(result == bytes.len() as c_int).then_some(wc)
+	}
+
+	#[test]
+	fn valid_sequences() {
+		assert_eq!(decode(b"A"), Some(0x41));
+		assert_eq!(decode("\u{e9}".as_bytes()), Some(0xE9));
+		assert_eq!(decode("\u{20AC}".as_bytes()), Some(0x20AC));
+		assert_eq!(decode("\u{FFFD}".as_bytes()), Some(0xFFFD));
+		let astral = to_wchar(0x1F600);
+		assert_eq!(decode("\u{1F600}".as_bytes()), astral);
+		assert_eq!(decode("\u{10FFFF}".as_bytes()), to_wchar(0x10FFFF));
+		// These only fit in a 32-bit wchar_t
+		assert_eq!(astral.is_some(), core::mem::size_of::<wchar_t>() == 4);
+	}
+
+	#[test]
+	fn null_character() {
+		let mut wc: wchar_t = 1;
+		assert_eq!(unsafe { mbtowc(&mut wc, c"".as_ptr(), 1) }, 0);
+		assert_eq!(wc, 0);
+		assert_eq!(unsafe { mblen(core::ptr::null(), 0) }, 0);
+	}
+
+	#[test]
+	fn rejects_overlong() {
+		let _guard = crate::test_lock();
+		assert_eq!(decode(&[0xC0, 0x80]), None);
+		assert_eq!(decode(&[0xC1, 0xBF]), None);
+		assert_eq!(decode(&[0xE0, 0x80, 0x80]), None);
+		assert_eq!(decode(&[0xE0, 0x9F, 0xBF]), None);
+		assert_eq!(decode(&[0xF0, 0x8F, 0xBF, 0xBF]), None);
+	}
+
+	#[test]
+	fn rejects_surrogates_and_out_of_range() {
+		let _guard = crate::test_lock();
+		assert_eq!(decode(&[0xED, 0xA0, 0x80]), None);
+		assert_eq!(decode(&[0xED, 0xBF, 0xBF]), None);
+		assert_eq!(decode(&[0xF4, 0x90, 0x80, 0x80]), None);
+		assert_eq!(decode(&[0xF5, 0x80, 0x80, 0x80]), None);
+		assert_eq!(decode(&[0xFF]), None);
+		assert_eq!(decode(&[0x80]), None);
+	}
+
+	#[test]
+	fn sets_eilseq() {
+		let _guard = crate::test_lock();
+		let mut wc: wchar_t = 0;
+		let mut buffer = [0 as c_char; MB_LEN_MAX];
+		unsafe {
+			set_errno(0);
+			assert_eq!(mbtowc(&mut wc, [0xC0u8, 0x80].as_ptr().cast(), 2), -1);
+			assert_eq!(errno(), EILSEQ);
+			// An incomplete character is an error for mbtowc
+			set_errno(0);
+			assert_eq!(mbtowc(&mut wc, [0xE2u8, 0x82].as_ptr().cast(), 2), -1);
+			assert_eq!(errno(), EILSEQ);
+			set_errno(0);
+			assert_eq!(
+				wcrtomb(buffer.as_mut_ptr(), 0xDC00, core::ptr::null_mut()),
+				INVALID
+			);
+			assert_eq!(errno(), EILSEQ);
+			set_errno(0);
+			let wide: [wchar_t; 2] = [0xD800, 0];
+			assert_eq!(wcstombs(core::ptr::null_mut(), wide.as_ptr(), 0), INVALID);
+			assert_eq!(errno(), EILSEQ);
+		}
+	}
+
+	#[test]
+	fn early_rejection() {
+		let _guard = crate::test_lock();
+		// The second byte already proves this is a surrogate
+		let mut state = mbstate_t::new();
+		let result = unsafe {
+			mbrtowc(
+				core::ptr::null_mut(),
+				[0xEDu8, 0xA0].as_ptr().cast(),
+				2,
+				&mut state,
+			)
+		};
+		assert_eq!(result, INVALID);
+		assert!(state.is_initial());
+	}
+
+	#[test]
+	fn restartable() {
+		let bytes = "\u{20AC}".as_bytes();
+		let mut state = mbstate_t::new();
+		let mut wc: wchar_t = 0;
+		unsafe {
+			assert_eq!(
+				mbrtowc(&mut wc, bytes.as_ptr().cast(), 1, &mut state),
+				INCOMPLETE
+			);
+			assert_eq!(mbsinit(&state), 0);
+			assert_eq!(
+				mbrtowc(&mut wc, bytes[1..].as_ptr().cast(), 1, &mut state),
+				INCOMPLETE
+			);
+			assert_eq!(
+				mbrtowc(&mut wc, bytes[2..].as_ptr().cast(), 1, &mut state),
+				1
+			);
+			assert_eq!(mbsinit(&state), 1);
+		}
+		assert_eq!(wc, 0x20AC);
+	}
+
+	#[test]
+	fn encode_characters() {
+		let _guard = crate::test_lock();
+		let mut buffer = [0 as c_char; MB_LEN_MAX];
+		unsafe {
+			assert_eq!(wctomb(buffer.as_mut_ptr(), 0x20AC), 3);
+			assert_eq!(
+				&buffer[..3],
+				[0xE2u8 as c_char, 0x82u8 as c_char, 0xACu8 as c_char]
+			);
+			assert_eq!(wctomb(buffer.as_mut_ptr(), 0xD800), -1);
+			assert_eq!(wctomb(core::ptr::null_mut(), 0x41), 0);
+		}
+	}
+
+	#[test]
+	fn string_round_trip() {
+		let text = c"caf\xc3\xa9 \xe2\x82\xac5";
+		let mut wide = [0 as wchar_t; 16];
+		let count = unsafe { mbstowcs(wide.as_mut_ptr(), text.as_ptr(), wide.len()) };
+		assert_eq!(count, 7);
+		assert_eq!(wide[3], 0xE9);
+		assert_eq!(wide[5], 0x20AC);
+		assert_eq!(wide[7], 0);
+		assert_eq!(
+			unsafe { mbstowcs(core::ptr::null_mut(), text.as_ptr(), 0) },
+			7
+		);
+
+		let mut narrow = [0x55 as c_char; 16];
+		let len = unsafe { wcstombs(narrow.as_mut_ptr(), wide.as_ptr(), narrow.len()) };
+		assert_eq!(len, text.to_bytes().len());
+		assert_eq!(unsafe { core::ffi::CStr::from_ptr(narrow.as_ptr()) }, text);
+		assert_eq!(
+			unsafe { wcstombs(core::ptr::null_mut(), wide.as_ptr(), 0) },
+			len
+		);
+	}
+
+	#[test]
+	fn string_truncation() {
+		let wide: [wchar_t; 3] = [0x41, 0x20AC, 0];
+		let mut narrow = [0x55 as c_char; 3];
+		let mut src = wide.as_ptr();
+		let len = unsafe {
+			wcsrtombs(
+				narrow.as_mut_ptr(),
+				&mut src,
+				narrow.len(),
+				core::ptr::null_mut(),
+			)
+		};
+		// The euro sign doesn't fit, so we stop before it
+		assert_eq!(len, 1);
+		assert_eq!(src, unsafe { wide.as_ptr().add(1) });
+	}
+
+	#[test]
+	fn string_invalid() {
+		let _guard = crate::test_lock();
+		let text = c"ok\xc0\x80";
+		let mut wide = [0 as wchar_t; 8];
+		let mut src = text.as_ptr();
+		let result = unsafe {
+			mbsrtowcs(
+				wide.as_mut_ptr(),
+				&mut src,
+				wide.len(),
+				core::ptr::null_mut(),
+			)
+		};
+		assert_eq!(result, INVALID);
+		assert_eq!(src, unsafe { text.as_ptr().add(2) });
+	}
+}
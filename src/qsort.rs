@@ -1,4 +1,4 @@
-//! Rust implementation of C library function `qsort`
+//! Rust implementation of C library functions `qsort`, `qsort_r` and `bsearch`
 //!
 //! This code snippet is copied from relibc.
 //! MIT license COPYRIGHT (c) 2018 Redox OS
@@ -25,20 +25,108 @@ pub unsafe extern "C" fn qsort(
 	compar: Option<extern "C" fn(*const c_void, *const c_void) -> c_int>,
 ) {
 	if let Some(comp) = compar {
-		if nel > 0 {
-			let maxdepth = MAXDEPTH_MULTIPLIER * nel.ilog2() as usize;
-			introsort_helper(base, nel, width, maxdepth, comp);
+		// Rust 1.77 doesn't implement `FnMut` for `extern "C"` functions, so
+		// this closure isn't redundant
+		#[allow(clippy::redundant_closure)]
+		introsort(base, nel, width, &mut |a, b| comp(a, b));
+	}
+}
+
+/// Rust implementation of the GNU C library function `qsort_r`
+///
+/// As [`qsort`], but `arg` is passed to every call to `compar(a, b, arg)`.
+/// This argument order is also used by POSIX and by FreeBSD 14 onwards.
+///
+/// Not exported if the `qsort_r_bsd` feature selects the BSD argument order.
+#[cfg_attr(all(feature = "qsort_r", not(feature = "qsort_r_bsd")), no_mangle)]
+pub unsafe extern "C" fn qsort_r(
+	base: *mut c_void,
+	nel: usize,
+	width: usize,
+	compar: Option<extern "C" fn(*const c_void, *const c_void, *mut c_void) -> c_int>,
+	arg: *mut c_void,
+) {
+	if let Some(comp) = compar {
+		introsort(base, nel, width, &mut |a, b| comp(a, b, arg));
+	}
+}
+
+/// Rust implementation of the BSD C library function `qsort_r`
+///
+/// As [`qsort`], but `thunk` is passed as the first argument to every call
+/// to `compar(thunk, a, b)`. Exported as `qsort_r` when the `qsort_r_bsd`
+/// feature is enabled (along with `qsort_r`).
+#[cfg_attr(
+	all(feature = "qsort_r", feature = "qsort_r_bsd"),
+	export_name = "qsort_r"
+)]
+pub unsafe extern "C" fn qsort_r_bsd(
+	base: *mut c_void,
+	nel: usize,
+	width: usize,
+	thunk: *mut c_void,
+	compar: Option<extern "C" fn(*mut c_void, *const c_void, *const c_void) -> c_int>,
+) {
+	if let Some(comp) = compar {
+		introsort(base, nel, width, &mut |a, b| comp(thunk, a, b));
+	}
+}
+
+/// Rust implementation of C library function `bsearch`
+///
+/// Searches the sorted array `base` for an element matching `key`.
+/// compar(key, element) returns a negative value if key < element, 0 if they
+/// match, and a positive value if key > element.
+///
+/// Returns a pointer to a matching element, or NULL if there isn't one.
+#[cfg_attr(feature = "bsearch", no_mangle)]
+pub unsafe extern "C" fn bsearch(
+	key: *const c_void,
+	base: *const c_void,
+	nel: usize,
+	width: usize,
+	compar: Option<extern "C" fn(*const c_void, *const c_void) -> c_int>,
+) -> *mut c_void {
+	let Some(comp) = compar else {
+		return core::ptr::null_mut();
+	};
+	let mut low = 0;
+	let mut high = nel;
+	while low < high {
+		let mid = low + (high - low) / 2;
+		let element = base.add(mid * width);
+		match comp(key, element).cmp(&0) {
+			core::cmp::Ordering::Less => high = mid,
+			core::cmp::Ordering::Greater => low = mid + 1,
+			core::cmp::Ordering::Equal => return element.cast_mut(),
 		}
 	}
+	core::ptr::null_mut()
 }
 
-fn introsort_helper(
+/// Sort `nel` elements of `width` bytes, starting at `base`, using `comp` to
+/// compare them.
+///
+/// This is shared by the C functions above and by [`crate::sort`].
+pub(crate) fn introsort<F>(base: *mut c_void, nel: usize, width: usize, comp: &mut F)
+where
+	F: FnMut(*const c_void, *const c_void) -> c_int,
+{
+	if nel > 0 {
+		let maxdepth = MAXDEPTH_MULTIPLIER * nel.ilog2() as usize;
+		introsort_helper(base, nel, width, maxdepth, comp);
+	}
+}
+
+fn introsort_helper<F>(
 	mut base: *mut c_void,
 	mut nel: usize,
 	width: usize,
 	mut maxdepth: usize,
-	comp: extern "C" fn(*const c_void, *const c_void) -> c_int,
-) {
+	comp: &mut F,
+) where
+	F: FnMut(*const c_void, *const c_void) -> c_int,
+{
 	// This loop is a trick to save stack space because TCO is not a thing in Rustland.
 	// Basically, we just change the arguments and loop rather than recursing for the second call
 	// to introsort_helper().
@@ -68,12 +156,10 @@ fn introsort_helper(
 	}
 }
 
-fn insertion_sort(
-	base: *mut c_void,
-	nel: usize,
-	width: usize,
-	comp: extern "C" fn(*const c_void, *const c_void) -> c_int,
-) {
+fn insertion_sort<F>(base: *mut c_void, nel: usize, width: usize, comp: &mut F)
+where
+	F: FnMut(*const c_void, *const c_void) -> c_int,
+{
 	for i in 0..nel {
 		for j in (0..i).rev() {
 			let current = unsafe { base.add(j * width) };
@@ -87,12 +173,10 @@ fn insertion_sort(
 	}
 }
 
-fn heapsort(
-	base: *mut c_void,
-	nel: usize,
-	width: usize,
-	comp: extern "C" fn(*const c_void, *const c_void) -> c_int,
-) {
+fn heapsort<F>(base: *mut c_void, nel: usize, width: usize, comp: &mut F)
+where
+	F: FnMut(*const c_void, *const c_void) -> c_int,
+{
 	heapify(base, nel, width, comp);
 
 	let mut end = nel - 1;
@@ -104,12 +188,10 @@ fn heapsort(
 	}
 }
 
-fn heapify(
-	base: *mut c_void,
-	nel: usize,
-	width: usize,
-	comp: extern "C" fn(*const c_void, *const c_void) -> c_int,
-) {
+fn heapify<F>(base: *mut c_void, nel: usize, width: usize, comp: &mut F)
+where
+	F: FnMut(*const c_void, *const c_void) -> c_int,
+{
 	// we start at the last parent in the heap (the parent of the last child)
 	let last_parent = (nel - 2) / 2;
 
@@ -118,13 +200,10 @@ fn heapify(
 	}
 }
 
-fn heap_sift_down(
-	base: *mut c_void,
-	start: usize,
-	end: usize,
-	width: usize,
-	comp: extern "C" fn(*const c_void, *const c_void) -> c_int,
-) {
+fn heap_sift_down<F>(base: *mut c_void, start: usize, end: usize, width: usize, comp: &mut F)
+where
+	F: FnMut(*const c_void, *const c_void) -> c_int,
+{
 	// get the left child of the node at the given index
 	let left_child = |idx| 2 * idx + 1;
 
@@ -158,12 +237,10 @@ fn heap_sift_down(
 }
 
 #[inline]
-fn partition(
-	base: *mut c_void,
-	nel: usize,
-	width: usize,
-	comp: extern "C" fn(*const c_void, *const c_void) -> c_int,
-) -> (usize, usize) {
+fn partition<F>(base: *mut c_void, nel: usize, width: usize, comp: &mut F) -> (usize, usize)
+where
+	F: FnMut(*const c_void, *const c_void) -> c_int,
+{
 	// calculate the median of the first, middle, and last elements and use it as the pivot
 	// to do fewer comparisons, also swap the elements into their correct positions
 	let mut pivot = median_of_three(base, nel, width, comp);
@@ -205,12 +282,10 @@ fn partition(
 	(i, n)
 }
 
-fn median_of_three(
-	base: *mut c_void,
-	nel: usize,
-	width: usize,
-	comp: extern "C" fn(*const c_void, *const c_void) -> c_int,
-) -> usize {
+fn median_of_three<F>(base: *mut c_void, nel: usize, width: usize, comp: &mut F) -> usize
+where
+	F: FnMut(*const c_void, *const c_void) -> c_int,
+{
 	let pivot = nel / 2;
 
 	let mid = unsafe { base.add(pivot * width) };
@@ -252,7 +327,7 @@ fn swap(ptr1: *mut c_void, ptr2: *mut c_void, mut width: usize) {
 			ptr1 = ptr1.add(copy_size);
 			ptr2 = ptr2.add(copy_size);
 		}
-		width -= copy_size as usize;
+		width -= copy_size;
 	}
 }
 
@@ -272,8 +347,8 @@ mod tests {
 		unsafe {
 			qsort(
 				array.as_mut_ptr() as *mut c_void,
-				array.len() as usize,
-				std::mem::size_of::<i32>() as usize,
+				array.len(),
+				std::mem::size_of::<i32>(),
 				Some(comp),
 			);
 		}
@@ -282,15 +357,16 @@ mod tests {
 	}
 
 	#[test]
+	#[allow(clippy::redundant_closure)] // as in `qsort`
 	fn identity_heapsort() {
 		let mut array: Vec<_> = (0..1000).collect();
 		let orig = array.clone();
 
 		heapsort(
 			array.as_mut_ptr() as *mut c_void,
-			array.len() as usize,
-			std::mem::size_of::<i32>() as usize,
-			comp,
+			array.len(),
+			std::mem::size_of::<i32>(),
+			&mut |a, b| comp(a, b),
 		);
 
 		assert_eq!(array, orig);
@@ -305,8 +381,8 @@ mod tests {
 		unsafe {
 			qsort(
 				array.as_mut_ptr() as *mut c_void,
-				array.len() as usize,
-				std::mem::size_of::<i32>() as usize,
+				array.len(),
+				std::mem::size_of::<i32>(),
 				Some(comp),
 			)
 		}
@@ -315,6 +391,7 @@ mod tests {
 	}
 
 	#[test]
+	#[allow(clippy::redundant_closure)] // as in `qsort`
 	fn reverse_heapsort() {
 		let mut array: Vec<_> = (0..1000).collect();
 		array.reverse();
@@ -322,9 +399,9 @@ mod tests {
 
 		heapsort(
 			array.as_mut_ptr() as *mut c_void,
-			array.len() as usize,
-			std::mem::size_of::<i32>() as usize,
-			comp,
+			array.len(),
+			std::mem::size_of::<i32>(),
+			&mut |a, b| comp(a, b),
 		);
 
 		assert_eq!(array, orig);
@@ -340,12 +417,12 @@ mod tests {
 
 	#[test]
 	fn random() {
-		let mut array = RAND_ARRAY.clone();
+		let mut array = RAND_ARRAY;
 		unsafe {
 			qsort(
 				array.as_mut_ptr() as *mut c_void,
-				array.len() as usize,
-				std::mem::size_of::<i32>() as usize,
+				array.len(),
+				std::mem::size_of::<i32>(),
 				Some(comp),
 			)
 		}
@@ -354,15 +431,94 @@ mod tests {
 	}
 
 	#[test]
+	#[allow(clippy::redundant_closure)] // as in `qsort`
 	fn random_heapsort() {
-		let mut array = RAND_ARRAY.clone();
+		let mut array = RAND_ARRAY;
 		heapsort(
 			array.as_mut_ptr() as *mut c_void,
-			array.len() as usize,
-			std::mem::size_of::<i32>() as usize,
-			comp,
+			array.len(),
+			std::mem::size_of::<i32>(),
+			&mut |a, b| comp(a, b),
 		);
 
 		assert_eq!(array, (0..100).collect::<Vec<_>>().as_slice());
 	}
+
+	/// Compares `i32`s, counting calls in the `usize` that `arg` points to
+	extern "C" fn comp_r(a: *const c_void, b: *const c_void, arg: *mut c_void) -> c_int {
+		unsafe { *(arg as *mut usize) += 1 };
+		comp(a, b)
+	}
+
+	/// As `comp_r`, but with the BSD argument order
+	extern "C" fn comp_r_bsd(thunk: *mut c_void, a: *const c_void, b: *const c_void) -> c_int {
+		comp_r(a, b, thunk)
+	}
+
+	#[test]
+	fn random_qsort_r() {
+		let mut array = RAND_ARRAY;
+		let mut calls = 0usize;
+		unsafe {
+			qsort_r(
+				array.as_mut_ptr() as *mut c_void,
+				array.len(),
+				std::mem::size_of::<i32>(),
+				Some(comp_r),
+				&mut calls as *mut usize as *mut c_void,
+			)
+		}
+
+		assert_eq!(array, (0..100).collect::<Vec<_>>().as_slice());
+		assert!(calls > 0);
+	}
+
+	#[test]
+	fn random_qsort_r_bsd() {
+		let mut array = RAND_ARRAY;
+		let mut calls = 0usize;
+		unsafe {
+			qsort_r_bsd(
+				array.as_mut_ptr() as *mut c_void,
+				array.len(),
+				std::mem::size_of::<i32>(),
+				&mut calls as *mut usize as *mut c_void,
+				Some(comp_r_bsd),
+			)
+		}
+
+		assert_eq!(array, (0..100).collect::<Vec<_>>().as_slice());
+		assert!(calls > 0);
+	}
+
+	fn search(array: &[i32], key: i32) -> Option<usize> {
+		let result = unsafe {
+			bsearch(
+				&key as *const i32 as *const c_void,
+				array.as_ptr() as *const c_void,
+				array.len(),
+				std::mem::size_of::<i32>(),
+				Some(comp),
+			)
+		};
+		(!result.is_null())
+			.then(|| unsafe { (result as *const i32).offset_from(array.as_ptr()) } as usize)
+	}
+
+	#[test]
+	fn bsearch_found() {
+		let array: Vec<i32> = (0..100).map(|x| x * 2).collect();
+		assert_eq!(search(&array, 0), Some(0));
+		assert_eq!(search(&array, 42), Some(21));
+		assert_eq!(search(&array, 198), Some(99));
+	}
+
+	#[test]
+	fn bsearch_not_found() {
+		let array: Vec<i32> = (0..100).map(|x| x * 2).collect();
+		assert_eq!(search(&array, -1), None);
+		assert_eq!(search(&array, 43), None);
+		assert_eq!(search(&array, 200), None);
+		assert_eq!(search(&[], 1), None);
+	}
 }
@@ -25,6 +25,8 @@ pub unsafe extern "C" fn rand_r(seedp: *mut c_uint) -> c_int {
 		((state >> 16) & ((1 << bits) - 1)) as c_int
 	}
 
+	// we do this cast to support platforms where c_uint is u16.
+	#[allow(clippy::unnecessary_cast)]
 	let mut next = *seedp as u32;
 	if c_int::MAX == 32767 || cfg!(feature = "rand_max_i16") {
 		// pull 15 bits in one go
@@ -0,0 +1,249 @@
+//! Rust implementation of C library functions `sbrk`, `_sbrk` and `brk`.
+//!
+//! The "program break" moves up and down within one region of memory, given
+//! by [`sbrk_init`] or (with the `sbrk-linker-symbols` feature) by the
+//! `__heap_start` and `__heap_end` linker symbols. `_sbrk` is what newlib's
+//! `malloc` calls, so prebuilt newlib objects can use this too.
+//!
+//! With the `heap` feature, our heap takes more memory from the break when it
+//! runs out, so memory handed out by the heap and by `sbrk` never overlaps.
+//! The break can't be moved back below memory the heap has taken.
+//!
+//! Licensed under the Blue Oak Model Licence 1.0.0
+
+use core::ffi::{c_int, c_void};
+
+use crate::errno::{set_errno, ENOMEM};
+use crate::spinlock::SpinLock;
+
+/// The region the break moves within, and the break itself.
+struct Region {
+	end: usize,
+	current: usize,
+	/// The lowest the break can go. Memory below it belongs to the heap.
+	floor: usize,
+}
+
+impl Region {
+	const fn new(start: usize, end: usize) -> Region {
+		Region {
+			end,
+			current: start,
+			floor: start,
+		}
+	}
+
+	/// Move the break to `new`, returning the old break, or `None` if `new`
+	/// is outside the region or below the floor.
+	fn move_to(&mut self, new: usize) -> Option<usize> {
+		if !(self.floor..=self.end).contains(&new) {
+			return None;
+		}
+		Some(core::mem::replace(&mut self.current, new))
+	}
+}
+
+/// The break, behind a lock. The region is `None` until it is known.
+static BREAK: SpinLock<Option<Region>> = SpinLock::new(None);
+
+/// Run `f` with the region locked, using the default region if it hasn't
+/// been set. Returns `None` if there is no region.
+fn with_region<R>(f: impl FnOnce(&mut Region) -> Option<R>) -> Option<R> {
+	BREAK.with(|region| {
+		if region.is_none() {
+			let (start, size) = default_region()?;
+			let start = start as usize;
+			*region = Some(Region::new(start, start.saturating_add(size)));
+		}
+		region.as_mut().and_then(f)
+	})
+}
+
+/// Forget the region, between tests.
+#[cfg(test)]
+pub(crate) fn reset() {
+	BREAK.with(|region| *region = None);
+}
+
+/// The region the break moves within, if [`sbrk_init`] is not called before
+/// the first call to `sbrk`.
+#[cfg(all(feature = "sbrk-linker-symbols", not(test)))]
+fn default_region() -> Option<(*mut u8, usize)> {
+	extern "C" {
+		static mut __heap_start: u8;
+		static mut __heap_end: u8;
+	}
+	// Older compilers need `unsafe` to take the address of an extern static
+	#[allow(unused_unsafe)]
+	let (start, end) = unsafe {
+		(
+			core::ptr::addr_of_mut!(__heap_start),
+			core::ptr::addr_of_mut!(__heap_end),
+		)
+	};
+	Some((start, (end as usize).saturating_sub(start as usize)))
+}
+
+/// The region the break moves within, if [`sbrk_init`] is not called before
+/// the first call to `sbrk`.
+#[cfg(not(all(feature = "sbrk-linker-symbols", not(test))))]
+fn default_region() -> Option<(*mut u8, usize)> {
+	None
+}
+
+/// The value `sbrk` returns on failure, `(void *) -1`
+const SBRK_FAILED: *mut c_void = usize::MAX as *mut c_void;
+
+/// Set the region the break moves within, and put the break at the start of
+/// it.
+///
+/// # Safety
+///
+/// The region must be valid for reads and writes, and must not be used for
+/// anything else. Any memory previously handed out by `sbrk` is forgotten.
+pub unsafe fn sbrk_init(start: *mut u8, size: usize) {
+	let start = start as usize;
+	BREAK.with(|region| *region = Some(Region::new(start, start.saturating_add(size))));
+}
+
+/// C interface to [`sbrk_init`].
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn tinyrlibc_sbrk_init(start: *mut c_void, size: usize) {
+	sbrk_init(start.cast(), size);
+}
+
+/// Move the break up by `increment` bytes, returning the start of the new
+/// memory, or `None` if there isn't enough.
+///
+/// The memory is the heap's for good: `sbrk` and `brk` can't move the break
+/// back below it.
+#[cfg(any(feature = "heap", test))]
+pub(crate) fn grow(increment: usize) -> Option<*mut u8> {
+	let old = with_region(|region| {
+		let old = region.move_to(region.current.checked_add(increment)?)?;
+		region.floor = region.current;
+		Some(old)
+	})?;
+	Some(old as *mut u8)
+}
+
+/// Rust implementation of C library function `sbrk`
+///
+/// Moves the break by `increment` bytes (which may be negative), and returns
+/// the old break. If the break would leave its region, or go below memory
+/// the heap has taken, it doesn't move, `(void *) -1` is returned and `errno`
+/// is set to `ENOMEM`.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn sbrk(increment: isize) -> *mut c_void {
+	match with_region(|region| region.move_to(region.current.checked_add_signed(increment)?)) {
+		Some(old) => old as *mut c_void,
+		None => {
+			set_errno(ENOMEM);
+			SBRK_FAILED
+		}
+	}
+}
+
+/// Rust implementation of newlib system call `_sbrk`
+///
+/// This is the same as [`sbrk`].
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn _sbrk(increment: isize) -> *mut c_void {
+	sbrk(increment)
+}
+
+/// Rust implementation of C library function `brk`
+///
+/// Sets the break to `addr`, returning 0. If `addr` is outside the break's
+/// region, or below memory the heap has taken, the break doesn't move, -1 is
+/// returned and `errno` is set to `ENOMEM`.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn brk(addr: *mut c_void) -> c_int {
+	match with_region(|region| region.move_to(addr as usize)) {
+		Some(_) => 0,
+		None => {
+			set_errno(ENOMEM);
+			-1
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::errno::errno;
+
+	#[test]
+	fn no_region() {
+		let _guard = crate::test_lock();
+		set_errno(0);
+		assert_eq!(sbrk(0), SBRK_FAILED);
+		assert_eq!(errno(), ENOMEM);
+		assert_eq!(grow(16), None);
+	}
+
+	#[test]
+	fn moves_break() {
+		let _guard = crate::test_lock();
+		let mut region = [0u8; 256];
+		let start = region.as_mut_ptr();
+		unsafe { sbrk_init(start, 256) };
+
+		assert_eq!(sbrk(0), start.cast());
+		assert_eq!(sbrk(100), start.cast());
+		assert_eq!(_sbrk(28), unsafe { start.add(100) }.cast());
+		assert_eq!(sbrk(28), unsafe { start.add(128) }.cast());
+		assert_eq!(sbrk(-56), unsafe { start.add(156) }.cast());
+		assert_eq!(sbrk(0), unsafe { start.add(100) }.cast());
+
+		assert_eq!(brk(unsafe { start.add(256) }.cast()), 0);
+		assert_eq!(sbrk(0), unsafe { start.add(256) }.cast());
+	}
+
+	#[test]
+	fn stays_in_region() {
+		let _guard = crate::test_lock();
+		let mut region = [0u8; 256];
+		let start = region.as_mut_ptr();
+		unsafe { sbrk_init(start, 256) };
+		assert_eq!(sbrk(200), start.cast());
+
+		set_errno(0);
+		assert_eq!(sbrk(57), SBRK_FAILED);
+		assert_eq!(errno(), ENOMEM);
+		assert_eq!(grow(57), None);
+		assert_eq!(sbrk(-201), SBRK_FAILED);
+		assert_eq!(sbrk(isize::MIN), SBRK_FAILED);
+		set_errno(0);
+		assert_eq!(brk(unsafe { start.add(257) }.cast()), -1);
+		assert_eq!(errno(), ENOMEM);
+		assert_eq!(brk(unsafe { start.sub(1) }.cast()), -1);
+
+		// The break hasn't moved
+		assert_eq!(sbrk(56), unsafe { start.add(200) }.cast());
+		assert_eq!(sbrk(0), unsafe { start.add(256) }.cast());
+	}
+
+	#[test]
+	fn keeps_heap_memory() {
+		let _guard = crate::test_lock();
+		let mut region = [0u8; 256];
+		let start = region.as_mut_ptr();
+		unsafe { sbrk_init(start, 256) };
+		assert_eq!(sbrk(16), start.cast());
+		assert_eq!(grow(48), Some(unsafe { start.add(16) }));
+
+		set_errno(0);
+		assert_eq!(sbrk(-1), SBRK_FAILED);
+		assert_eq!(errno(), ENOMEM);
+		set_errno(0);
+		assert_eq!(brk(start.cast()), -1);
+		assert_eq!(errno(), ENOMEM);
+
+		// Memory above the heap's can still be given back
+		assert_eq!(sbrk(32), unsafe { start.add(64) }.cast());
+		assert_eq!(sbrk(-32), unsafe { start.add(96) }.cast());
+		assert_eq!(brk(unsafe { start.add(64) }.cast()), 0);
+		assert_eq!(sbrk(0), unsafe { start.add(64) }.cast());
+	}
+}
@@ -0,0 +1,597 @@
+//! Rust implementation of the C library functions `setjmp`, `longjmp`,
+//! `sigsetjmp` and `siglongjmp`.
+//!
+//! These can't be written in Rust, so `setjmp`, `longjmp` and `sigsetjmp`
+//! are in assembly, for Arm (Thumb), RISC-V and x86_64. Each `jmp_buf` is the
+//! same size as the one in the usual C library for that target (newlib, or
+//! glibc on x86_64), so C code built with those headers works with ours.
+//!
+//! With the `signal` feature, `sigsetjmp` can save the signal mask from
+//! `sigprocmask`, for `siglongjmp` to put back.
+//!
+//! `setjmp` and `sigsetjmp` are only for C: Rust has no way to say that a
+//! function returns twice, so the compiler could keep values in registers
+//! which the second return clobbers. `longjmp` and `siglongjmp` can be
+//! called from Rust, to jump back to C.
+//!
+//! Licensed under the Blue Oak Model Licence 1.0.0
+
+use core::ffi::{c_int, c_long};
+
+/// A buffer for `setjmp` (x86_64, as glibc)
+#[cfg(target_arch = "x86_64")]
+#[allow(non_camel_case_types)]
+pub type jmp_buf = [i64; 25];
+
+/// A buffer for `setjmp` (Arm, as newlib)
+#[cfg(target_arch = "arm")]
+#[allow(non_camel_case_types)]
+pub type jmp_buf = [i64; 23];
+
+/// A buffer for `setjmp` (RISC-V, as newlib). Like newlib's, it holds
+/// `long long`s, so that the FPU registers are 8-byte aligned on riscv32.
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+#[allow(non_camel_case_types)]
+pub type jmp_buf =
+	[u64; (14 * core::mem::size_of::<c_long>() + 12 * 8) / core::mem::size_of::<c_long>()];
+
+/// A signal mask, as saved by `sigsetjmp`.
+#[cfg(all(not(target_arch = "x86_64"), feature = "signal"))]
+type SavedMask = crate::signal::sigset_t;
+
+/// A signal mask, as saved by `sigsetjmp` (which can't save one without the
+/// `signal` feature).
+#[cfg(all(not(target_arch = "x86_64"), not(feature = "signal")))]
+type SavedMask = core::ffi::c_ulong;
+
+/// A buffer for `sigsetjmp` (x86_64, as glibc): the same size as a
+/// [`jmp_buf`], with the signal mask in the words `setjmp` doesn't use.
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
+pub struct sigjmp_buf {
+	/// The registers, as saved by `setjmp`
+	pub __jmpbuf: [i64; 8],
+	/// Non-zero if `__saved_mask` holds the signal mask
+	pub __mask_was_saved: c_int,
+	/// The signal mask, in the first `sigset_t` of glibc's 1024 bits
+	pub __saved_mask: [u64; 16],
+}
+
+/// A buffer for `sigsetjmp`: a [`jmp_buf`] followed by two words, as in
+/// newlib.
+///
+/// With the `signal-rt` feature, the mask is a 64-bit `sigset_t`, so this is
+/// bigger than newlib's on 32-bit targets.
+#[cfg(not(target_arch = "x86_64"))]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
+pub struct sigjmp_buf {
+	/// The registers, as saved by `setjmp`
+	pub __jmpbuf: jmp_buf,
+	/// Non-zero if `__saved_mask` holds the signal mask
+	pub __mask_was_saved: c_long,
+	/// The signal mask (a `sigset_t`)
+	pub __saved_mask: SavedMask,
+}
+
+/// The name of a function written in assembly. Tests get their own, so they
+/// don't replace the OS's.
+#[cfg(not(test))]
+macro_rules! symbol {
+	($name:literal) => {
+		$name
+	};
+}
+
+/// The name of a function written in assembly. Tests get their own, so they
+/// don't replace the OS's.
+#[cfg(test)]
+macro_rules! symbol {
+	($name:literal) => {
+		concat!("tinyrlibc_test_", $name)
+	};
+}
+
+/// The start of a function written in assembly.
+macro_rules! function {
+	($name:literal) => {
+		concat!(
+			".global ",
+			symbol!($name),
+			"\n.type ",
+			symbol!($name),
+			", %function\n",
+			symbol!($name),
+			":\n",
+		)
+	};
+}
+
+/// The start of a Thumb function written in assembly.
+#[cfg(target_arch = "arm")]
+macro_rules! thumb_function {
+	($name:literal) => {
+		concat!(".thumb_func\n", function!($name))
+	};
+}
+
+// x86_64 (System V): rbx, rbp, r12-r15, then the stack pointer and return
+// address, as the caller will see them when we return.
+#[cfg(target_arch = "x86_64")]
+macro_rules! save_registers {
+	() => {
+		"
+		mov [rdi], rbx
+		mov [rdi + 8], rbp
+		mov [rdi + 16], r12
+		mov [rdi + 24], r13
+		mov [rdi + 32], r14
+		mov [rdi + 40], r15
+		lea rdx, [rsp + 8]
+		mov [rdi + 48], rdx
+		mov rdx, [rsp]
+		mov [rdi + 56], rdx
+		"
+	};
+}
+
+#[cfg(target_arch = "x86_64")]
+core::arch::global_asm!(
+	".text",
+	function!("setjmp"),
+	save_registers!(),
+	"
+	xor eax, eax
+	ret
+	",
+	function!("sigsetjmp"),
+	save_registers!(),
+	"
+	jmp {save}
+	",
+	function!("longjmp"),
+	"
+	mov eax, esi
+	test eax, eax
+	jnz 1f
+	inc eax
+1:
+	mov rbx, [rdi]
+	mov rbp, [rdi + 8]
+	mov r12, [rdi + 16]
+	mov r13, [rdi + 24]
+	mov r14, [rdi + 32]
+	mov r15, [rdi + 40]
+	mov rsp, [rdi + 48]
+	jmp qword ptr [rdi + 56]
+	",
+	save = sym sigjmp_save,
+);
+
+// Arm: r4-r11, sp and lr, then (with a hardware FPU) d8-d15. Only Thumb-1
+// instructions are used, so this works on every Cortex-M. r0 is left
+// pointing at the buffer, and r1-r3 are left alone.
+#[cfg(all(target_arch = "arm", not(tinyrlibc_fpu = "double")))]
+macro_rules! save_registers {
+	() => {
+		"
+		stmia r0!, {{r4-r7}}
+		mov r4, r8
+		mov r5, r9
+		mov r6, r10
+		mov r7, r11
+		stmia r0!, {{r4-r7}}
+		mov r4, sp
+		mov r5, lr
+		stmia r0!, {{r4, r5}}
+		subs r0, #40
+		ldmia r0!, {{r4-r7}}
+		subs r0, #16
+		"
+	};
+}
+
+#[cfg(all(target_arch = "arm", tinyrlibc_fpu = "double"))]
+macro_rules! save_registers {
+	() => {
+		"
+		stmia r0!, {{r4-r7}}
+		mov r4, r8
+		mov r5, r9
+		mov r6, r10
+		mov r7, r11
+		stmia r0!, {{r4-r7}}
+		mov r4, sp
+		mov r5, lr
+		stmia r0!, {{r4, r5}}
+		vstmia r0!, {{d8-d15}}
+		subs r0, #104
+		ldmia r0!, {{r4-r7}}
+		subs r0, #16
+		"
+	};
+}
+
+#[cfg(all(target_arch = "arm", not(tinyrlibc_fpu = "double")))]
+macro_rules! load_fpu_registers {
+	() => {
+		"
+		subs r0, #40
+		"
+	};
+}
+
+#[cfg(all(target_arch = "arm", tinyrlibc_fpu = "double"))]
+macro_rules! load_fpu_registers {
+	() => {
+		"
+		vldmia r0!, {{d8-d15}}
+		subs r0, #104
+		"
+	};
+}
+
+#[cfg(target_arch = "arm")]
+core::arch::global_asm!(
+	".syntax unified",
+	".text",
+	".thumb",
+	thumb_function!("setjmp"),
+	save_registers!(),
+	"
+	movs r0, #0
+	bx lr
+	",
+	thumb_function!("sigsetjmp"),
+	save_registers!(),
+	"
+	ldr r2, ={save}
+	bx r2
+	.ltorg
+	",
+	thumb_function!("longjmp"),
+	"
+	adds r0, #16
+	ldmia r0!, {{r4-r7}}
+	mov r8, r4
+	mov r9, r5
+	mov r10, r6
+	mov r11, r7
+	ldmia r0!, {{r4, r5}}
+	mov sp, r4
+	mov lr, r5
+	",
+	load_fpu_registers!(),
+	"
+	ldmia r0!, {{r4-r7}}
+	movs r0, r1
+	bne 1f
+	movs r0, #1
+1:
+	bx lr
+	",
+	save = sym sigjmp_save,
+);
+
+// RISC-V: ra, sp and s0-s11, then (with a hardware FPU) fs0-fs11, each in
+// eight bytes.
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+macro_rules! riscv_registers {
+	($op:literal, $fop:literal) => {
+		concat!(
+			$op,
+			" ra, 0 * WORD(a0)\n",
+			$op,
+			" sp, 1 * WORD(a0)\n",
+			$op,
+			" s0, 2 * WORD(a0)\n",
+			$op,
+			" s1, 3 * WORD(a0)\n",
+			$op,
+			" s2, 4 * WORD(a0)\n",
+			$op,
+			" s3, 5 * WORD(a0)\n",
+			$op,
+			" s4, 6 * WORD(a0)\n",
+			$op,
+			" s5, 7 * WORD(a0)\n",
+			$op,
+			" s6, 8 * WORD(a0)\n",
+			$op,
+			" s7, 9 * WORD(a0)\n",
+			$op,
+			" s8, 10 * WORD(a0)\n",
+			$op,
+			" s9, 11 * WORD(a0)\n",
+			$op,
+			" s10, 12 * WORD(a0)\n",
+			$op,
+			" s11, 13 * WORD(a0)\n",
+			riscv_fpu_registers!($fop),
+		)
+	};
+}
+
+#[cfg(all(
+	any(target_arch = "riscv32", target_arch = "riscv64"),
+	any(tinyrlibc_fpu = "single", tinyrlibc_fpu = "double")
+))]
+macro_rules! riscv_fpu_registers {
+	($fop:literal) => {
+		concat!(
+			$fop,
+			" fs0, 14 * WORD + 0 * 8(a0)\n",
+			$fop,
+			" fs1, 14 * WORD + 1 * 8(a0)\n",
+			$fop,
+			" fs2, 14 * WORD + 2 * 8(a0)\n",
+			$fop,
+			" fs3, 14 * WORD + 3 * 8(a0)\n",
+			$fop,
+			" fs4, 14 * WORD + 4 * 8(a0)\n",
+			$fop,
+			" fs5, 14 * WORD + 5 * 8(a0)\n",
+			$fop,
+			" fs6, 14 * WORD + 6 * 8(a0)\n",
+			$fop,
+			" fs7, 14 * WORD + 7 * 8(a0)\n",
+			$fop,
+			" fs8, 14 * WORD + 8 * 8(a0)\n",
+			$fop,
+			" fs9, 14 * WORD + 9 * 8(a0)\n",
+			$fop,
+			" fs10, 14 * WORD + 10 * 8(a0)\n",
+			$fop,
+			" fs11, 14 * WORD + 11 * 8(a0)\n",
+		)
+	};
+}
+
+#[cfg(all(
+	any(target_arch = "riscv32", target_arch = "riscv64"),
+	not(any(tinyrlibc_fpu = "single", tinyrlibc_fpu = "double"))
+))]
+macro_rules! riscv_fpu_registers {
+	($fop:literal) => {
+		""
+	};
+}
+
+#[cfg(target_arch = "riscv32")]
+macro_rules! riscv_word {
+	() => {
+		".set WORD, 4"
+	};
+}
+
+#[cfg(target_arch = "riscv64")]
+macro_rules! riscv_word {
+	() => {
+		".set WORD, 8"
+	};
+}
+
+#[cfg(all(target_arch = "riscv32", not(tinyrlibc_fpu = "double")))]
+macro_rules! save_registers {
+	() => {
+		riscv_registers!("sw", "fsw")
+	};
+}
+
+#[cfg(all(target_arch = "riscv32", not(tinyrlibc_fpu = "double")))]
+macro_rules! load_registers {
+	() => {
+		riscv_registers!("lw", "flw")
+	};
+}
+
+#[cfg(all(target_arch = "riscv32", tinyrlibc_fpu = "double"))]
+macro_rules! save_registers {
+	() => {
+		riscv_registers!("sw", "fsd")
+	};
+}
+
+#[cfg(all(target_arch = "riscv32", tinyrlibc_fpu = "double"))]
+macro_rules! load_registers {
+	() => {
+		riscv_registers!("lw", "fld")
+	};
+}
+
+#[cfg(all(target_arch = "riscv64", not(tinyrlibc_fpu = "double")))]
+macro_rules! save_registers {
+	() => {
+		riscv_registers!("sd", "fsw")
+	};
+}
+
+#[cfg(all(target_arch = "riscv64", not(tinyrlibc_fpu = "double")))]
+macro_rules! load_registers {
+	() => {
+		riscv_registers!("ld", "flw")
+	};
+}
+
+#[cfg(all(target_arch = "riscv64", tinyrlibc_fpu = "double"))]
+macro_rules! save_registers {
+	() => {
+		riscv_registers!("sd", "fsd")
+	};
+}
+
+#[cfg(all(target_arch = "riscv64", tinyrlibc_fpu = "double"))]
+macro_rules! load_registers {
+	() => {
+		riscv_registers!("ld", "fld")
+	};
+}
+
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+core::arch::global_asm!(
+	".text",
+	riscv_word!(),
+	function!("setjmp"),
+	save_registers!(),
+	"
+	li a0, 0
+	ret
+	",
+	function!("sigsetjmp"),
+	save_registers!(),
+	"
+	tail {save}
+	",
+	function!("longjmp"),
+	load_registers!(),
+	"
+	seqz a0, a1
+	add a0, a0, a1
+	ret
+	",
+	save = sym sigjmp_save,
+);
+
+extern "C" {
+	/// Rust interface to the C library function `longjmp`.
+	///
+	/// Returns from the `setjmp` call which filled in `env`, which must
+	/// still be running, making it return `val` (or 1, if `val` is 0).
+	#[cfg_attr(test, link_name = "tinyrlibc_test_longjmp")]
+	pub fn longjmp(env: *mut jmp_buf, val: c_int) -> !;
+}
+
+// From `setjmp_test.c`, which calls `setjmp` and `sigsetjmp` for the tests
+#[cfg(test)]
+extern "C" {
+	/// Call `setjmp` on `env` from C, then `jump(env, val)`, which should
+	/// jump back. Returns what `setjmp` returned the second time, or -1 if
+	/// `jump` returned.
+	pub(crate) fn tinyrlibc_test_setjmp_and_jump(
+		env: *mut jmp_buf,
+		jump: unsafe extern "C" fn(*mut jmp_buf, c_int),
+		val: c_int,
+	) -> c_int;
+
+	/// Call `sigsetjmp` on `env` from C, then `jump(env, val)`, which should
+	/// jump back. Returns what `sigsetjmp` returned the second time, or -1 if
+	/// `jump` returned.
+	pub(crate) fn tinyrlibc_test_sigsetjmp_and_jump(
+		env: *mut sigjmp_buf,
+		savemask: c_int,
+		jump: unsafe extern "C" fn(*mut sigjmp_buf, c_int),
+		val: c_int,
+	) -> c_int;
+}
+
+/// Get a pointer to the signal mask in `env`.
+#[cfg(feature = "signal")]
+unsafe fn saved_mask(env: *mut sigjmp_buf) -> *mut crate::signal::sigset_t {
+	core::ptr::addr_of_mut!((*env).__saved_mask).cast()
+}
+
+/// The rest of `sigsetjmp`, which it jumps to once the registers are saved.
+unsafe extern "C" fn sigjmp_save(env: *mut sigjmp_buf, savemask: c_int) -> c_int {
+	(*env).__mask_was_saved = 0;
+	#[cfg(feature = "signal")]
+	if savemask != 0 {
+		let mask = saved_mask(env);
+		crate::signal::sigprocmask(crate::signal::SIG_BLOCK, core::ptr::null(), mask);
+		(*env).__mask_was_saved = 1;
+	}
+	#[cfg(not(feature = "signal"))]
+	let _ = savemask;
+	0
+}
+
+/// Rust implementation of the POSIX function `siglongjmp`.
+///
+/// This is [`longjmp`], but first puts back the signal mask, if
+/// `sigsetjmp` saved it.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn siglongjmp(env: *mut sigjmp_buf, val: c_int) -> ! {
+	#[cfg(feature = "signal")]
+	if (*env).__mask_was_saved != 0 {
+		let mask = saved_mask(env);
+		crate::signal::sigprocmask(crate::signal::SIG_SETMASK, mask, core::ptr::null_mut());
+	}
+	longjmp(core::ptr::addr_of_mut!((*env).__jmpbuf).cast(), val)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[inline(never)]
+	fn jump(env: *mut jmp_buf, depth: c_int, val: c_int) -> ! {
+		if depth == 0 {
+			unsafe { longjmp(env, val) }
+		}
+		jump(env, depth - 1, val)
+	}
+
+	/// Jump back to `env` from ten calls deep.
+	extern "C" fn jump_from_deep(env: *mut jmp_buf, val: c_int) {
+		jump(env, 10, val)
+	}
+
+	/// Jump back to `env` with `siglongjmp`.
+	extern "C" fn sigjump(env: *mut sigjmp_buf, val: c_int) {
+		unsafe { siglongjmp(env, val) }
+	}
+
+	#[test]
+	fn returns_twice() {
+		let mut env: jmp_buf = unsafe { core::mem::zeroed() };
+		let result = unsafe { tinyrlibc_test_setjmp_and_jump(&mut env, jump_from_deep, 42) };
+		assert_eq!(result, 42);
+	}
+
+	#[test]
+	fn zero_becomes_one() {
+		let mut env: jmp_buf = unsafe { core::mem::zeroed() };
+		let result = unsafe { tinyrlibc_test_setjmp_and_jump(&mut env, jump_from_deep, 0) };
+		assert_eq!(result, 1);
+	}
+
+	#[test]
+	#[cfg(target_arch = "x86_64")]
+	fn same_size_as_glibc() {
+		use core::mem::{align_of, size_of};
+		assert_eq!(size_of::<jmp_buf>(), 200);
+		assert_eq!(align_of::<jmp_buf>(), 8);
+		assert_eq!(size_of::<sigjmp_buf>(), 200);
+	}
+
+	#[test]
+	#[cfg(not(target_arch = "x86_64"))]
+	fn same_size_as_newlib() {
+		use core::mem::{align_of, size_of};
+		// newlib's jmp_buf is `long long[_JBLEN]`
+		#[cfg(target_arch = "arm")]
+		let jblen = 23;
+		#[cfg(target_arch = "riscv32")]
+		let jblen = 38;
+		#[cfg(target_arch = "riscv64")]
+		let jblen = 26;
+		assert_eq!(size_of::<jmp_buf>(), jblen * 8);
+		assert_eq!(align_of::<jmp_buf>(), 8);
+		#[cfg(not(feature = "signal-rt"))]
+		assert_eq!(
+			size_of::<sigjmp_buf>(),
+			size_of::<jmp_buf>() + 2 * size_of::<c_long>()
+		);
+	}
+
+	#[test]
+	fn sigsetjmp_without_mask() {
+		let mut env: sigjmp_buf = unsafe { core::mem::zeroed() };
+		env.__mask_was_saved = 1;
+		let result = unsafe { tinyrlibc_test_sigsetjmp_and_jump(&mut env, 0, sigjump, 7) };
+		assert_eq!(result, 7);
+		assert_eq!(env.__mask_was_saved, 0);
+	}
+}
@@ -0,0 +1,64 @@
+/**
+ * The C half of the `setjmp` tests.
+ *
+ * Rust has no way to say that a function returns twice, so it can't call
+ * `setjmp` or `sigsetjmp` itself. Instead, these functions call them, then
+ * call back into Rust, which jumps back with `longjmp` or `siglongjmp`.
+ *
+ * Licensed under the Blue Oak Model Licence 1.0.0
+ */
+
+/* ======================================================================== *
+ *
+ * Public Function Declarations
+ *
+ * ======================================================================== */
+
+/* The test builds of our assembly functions, from `setjmp.rs` */
+__attribute__((returns_twice)) int tinyrlibc_test_setjmp( void* env );
+__attribute__((returns_twice)) int tinyrlibc_test_sigsetjmp(
+   void* env, int savemask );
+
+/* A Rust function which jumps back to `env`, making it return `val` */
+typedef void ( *jump_fn )( void* env, int val );
+
+int tinyrlibc_test_setjmp_and_jump( void* env, jump_fn jump, int val );
+int tinyrlibc_test_sigsetjmp_and_jump(
+   void* env, int savemask, jump_fn jump, int val );
+
+/* ======================================================================== *
+ *
+ * Public Function Definitions
+ *
+ * ======================================================================== */
+
+/**
+ * Call `setjmp` on `env`, then `jump(env, val)`. Returns what `setjmp`
+ * returned the second time, or -1 if `jump` returned.
+ */
+int tinyrlibc_test_setjmp_and_jump( void* env, jump_fn jump, int val )
+{
+   int result = tinyrlibc_test_setjmp( env );
+   if ( result == 0 )
+   {
+      jump( env, val );
+      return -1;
+   }
+   return result;
+}
+
+/**
+ * Call `sigsetjmp` on `env`, then `jump(env, val)`. Returns what `sigsetjmp`
+ * returned the second time, or -1 if `jump` returned.
+ */
+int tinyrlibc_test_sigsetjmp_and_jump(
+   void* env, int savemask, jump_fn jump, int val )
+{
+   int result = tinyrlibc_test_sigsetjmp( env, savemask );
+   if ( result == 0 )
+   {
+      jump( env, val );
+      return -1;
+   }
+   return result;
+}
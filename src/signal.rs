@@ -3,8 +3,26 @@
 //! Copyright (c) Gyungmin Myung <gmmyung@kaist.ac.kr>
 //! Licensed under the Blue Oak Model Licence 1.0.0
 
-use core::{cell::RefCell, default};
-use portable_atomic::{AtomicUsize, Ordering};
+use core::ffi::{c_int, c_void};
+
+use portable_atomic::{AtomicI32, AtomicU32, AtomicUsize, Ordering};
+
+use crate::errno::{set_errno, EINVAL};
+
+/// The number of entries in our signal handler table.
+///
+/// Signal numbers go from 1 to `NSIG - 1`. The real-time signals are only
+/// included with the `signal-rt` feature, as they make the table twice as big.
+/// newlib has no real-time signals, so these numbers are our own.
+#[cfg(feature = "signal-rt")]
+pub const NSIG: usize = 65;
+
+/// The number of entries in our signal handler table.
+///
+/// Signal numbers go from 1 to `NSIG - 1`. The real-time signals are only
+/// included with the `signal-rt` feature, as they make the table twice as big.
+#[cfg(not(feature = "signal-rt"))]
+pub const NSIG: usize = 32;
 
 /// An initialiser for our array.
 ///
@@ -18,137 +36,758 @@ const SIG_DFL_ATOMIC: AtomicUsize = AtomicUsize::new(SIG_DFL);
 /// Signals in C are either 0, 1, -1, or a function pointer.
 ///
 /// We cast function pointers into `usize` so they can be stored in this array.
-static SIGNAL_HANDLERS: [AtomicUsize; 16] = [SIG_DFL_ATOMIC; 16];
+static SIGNAL_HANDLERS: [AtomicUsize; NSIG] = [SIG_DFL_ATOMIC; NSIG];
 
 /// A signal handler - either a function pointer or a magic integer.
 pub type SignalHandler = usize;
 
 /// Indicates we should use the default signal handler
-const SIG_DFL: usize = 0;
+pub const SIG_DFL: usize = 0;
 
-/// Indicates we should use the default signal handler
-const SIG_IGN: usize = 1;
+/// Indicates we should ignore the signal
+pub const SIG_IGN: usize = 1;
 
-/// Indicates we should use the default signal handler
-const SIG_ERR: usize = usize::MAX;
+/// Returned by `signal` on error
+pub const SIG_ERR: usize = usize::MAX;
 
-/// The TERM signal
-const SIGTERM: i32 = 15;
+// Signal numbers are the same as newlib's (which match the BSDs, but not
+// Linux, from SIGBUS on). The six ANSI C signals have the same numbers on
+// every common platform.
 
-/// The SEGV signal
-const SIGSEGV: i32 = 11;
+/// Hangup
+pub const SIGHUP: c_int = 1;
+/// Interactive attention (ANSI C)
+pub const SIGINT: c_int = 2;
+/// Quit
+pub const SIGQUIT: c_int = 3;
+/// Illegal instruction (ANSI C)
+pub const SIGILL: c_int = 4;
+/// Trace or breakpoint trap
+pub const SIGTRAP: c_int = 5;
+/// Abnormal termination (ANSI C)
+pub const SIGABRT: c_int = 6;
+/// Emulator trap
+pub const SIGEMT: c_int = 7;
+/// Erroneous arithmetic operation (ANSI C)
+pub const SIGFPE: c_int = 8;
+/// Kill (cannot be caught or ignored)
+pub const SIGKILL: c_int = 9;
+/// Bus error
+pub const SIGBUS: c_int = 10;
+/// Invalid memory access (ANSI C)
+pub const SIGSEGV: c_int = 11;
+/// Bad system call
+pub const SIGSYS: c_int = 12;
+/// Write to a pipe with no readers
+pub const SIGPIPE: c_int = 13;
+/// Alarm clock
+pub const SIGALRM: c_int = 14;
+/// Termination request (ANSI C)
+pub const SIGTERM: c_int = 15;
+/// Urgent data on a socket
+pub const SIGURG: c_int = 16;
+/// Stop (cannot be caught or ignored)
+pub const SIGSTOP: c_int = 17;
+/// Terminal stop
+pub const SIGTSTP: c_int = 18;
+/// Continue if stopped
+pub const SIGCONT: c_int = 19;
+/// Child process stopped or terminated
+pub const SIGCHLD: c_int = 20;
+/// Background read from terminal
+pub const SIGTTIN: c_int = 21;
+/// Background write to terminal
+pub const SIGTTOU: c_int = 22;
+/// I/O now possible
+pub const SIGIO: c_int = 23;
+/// CPU time limit exceeded
+pub const SIGXCPU: c_int = 24;
+/// File size limit exceeded
+pub const SIGXFSZ: c_int = 25;
+/// Virtual timer expired
+pub const SIGVTALRM: c_int = 26;
+/// Profiling timer expired
+pub const SIGPROF: c_int = 27;
+/// Window size change
+pub const SIGWINCH: c_int = 28;
+/// Resource lost
+pub const SIGLOST: c_int = 29;
+/// Power failure (the same as `SIGLOST`, as in newlib)
+pub const SIGPWR: c_int = SIGLOST;
+/// User-defined signal 1
+pub const SIGUSR1: c_int = 30;
+/// User-defined signal 2
+pub const SIGUSR2: c_int = 31;
+/// The first real-time signal
+#[cfg(feature = "signal-rt")]
+pub const SIGRTMIN: c_int = 32;
+/// The last real-time signal
+#[cfg(feature = "signal-rt")]
+pub const SIGRTMAX: c_int = NSIG as c_int - 1;
 
-/// The INT signal
-const SIGINT: i32 = 2;
+// SIGSEGV, SIGILL, SIGFPE and SIGBUS are not raised by the hardware on bare
+// metal. Instead, the application's fault handlers (HardFault, UsageFault,
+// RISC-V trap handlers, etc) call `tinyrlibc_deliver_fault`.
 
-/// The ILL signal
-const SIGILL: i32 = 4;
+/// Sent by `raise` (or `tinyrlibc_signal_post`)
+pub const SI_USER: c_int = 1;
+/// Sent by a fault handler which didn't say why (Linux's value - newlib
+/// doesn't have this)
+pub const SI_KERNEL: c_int = 0x80;
+/// SIGILL: illegal opcode
+pub const ILL_ILLOPC: c_int = 1;
+/// SIGILL: illegal operand
+pub const ILL_ILLOPN: c_int = 2;
+/// SIGILL: illegal addressing mode
+pub const ILL_ILLADR: c_int = 3;
+/// SIGFPE: integer divide by zero
+pub const FPE_INTDIV: c_int = 1;
+/// SIGFPE: integer overflow
+pub const FPE_INTOVF: c_int = 2;
+/// SIGSEGV: address not mapped
+pub const SEGV_MAPERR: c_int = 1;
+/// SIGSEGV: invalid permissions for mapped memory
+pub const SEGV_ACCERR: c_int = 2;
+/// SIGBUS: invalid address alignment
+pub const BUS_ADRALN: c_int = 1;
+/// SIGBUS: non-existent physical address
+pub const BUS_ADRERR: c_int = 2;
+
+/// A value sent with a signal.
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub union sigval {
+	/// The value as an integer
+	pub sival_int: c_int,
+	/// The value as a pointer
+	pub sival_ptr: *mut c_void,
+}
 
-/// The ABRT signal
-const SIGABRT: i32 = 6;
+/// Information about a signal, given to `SA_SIGINFO` handlers.
+///
+/// This starts with newlib's `siginfo_t`, and adds `si_addr` on the end.
+#[repr(C)]
+#[derive(Clone, Copy)]
+#[allow(non_camel_case_types)]
+pub struct siginfo_t {
+	/// The signal number
+	pub si_signo: c_int,
+	/// Why the signal was sent: `SI_USER`, `SI_KERNEL`, or a code for the
+	/// signal such as `SEGV_MAPERR`
+	pub si_code: c_int,
+	/// A value sent with the signal (always NULL from us)
+	pub si_value: sigval,
+	/// The address of the fault, for SIGSEGV, SIGBUS, SIGILL and SIGFPE
+	pub si_addr: *mut c_void,
+}
 
-/// The FPE signal
-const SIGFPE: i32 = 8;
+impl siginfo_t {
+	/// The information for a signal sent by `raise`.
+	const fn user(sig: c_int) -> siginfo_t {
+		siginfo_t {
+			si_signo: sig,
+			si_code: SI_USER,
+			si_value: sigval {
+				sival_ptr: core::ptr::null_mut(),
+			},
+			si_addr: core::ptr::null_mut(),
+		}
+	}
+}
 
-/// The list of support signals.
+/// A three-argument signal handler, used with `SA_SIGINFO`.
 ///
-/// Only ANSI C signals are now supported.
+/// The last argument would be the interrupted context, but is always NULL.
+pub type SigInfoHandler =
+	unsafe extern "C" fn(sig: c_int, info: *mut siginfo_t, context: *mut c_void);
+
+/// A set of signals, one bit per signal number (bit 0 is signal 1).
 ///
-/// SIGSEGV, SIGILL, SIGFPE are not supported on bare metal, but handlers are
-/// invoked when raise() is called.
+/// This is an `unsigned long`, as in newlib.
+#[cfg(not(feature = "signal-rt"))]
+#[allow(non_camel_case_types)]
+pub type sigset_t = core::ffi::c_ulong;
+
+/// A set of signals, one bit per signal number (bit 0 is signal 1).
 ///
-/// We will index `SIGNAL_HANDLERS` by any integer in this list, so ensure that
-/// the array is made larger if required.
+/// This is 64 bits, to fit the real-time signals, so it is wider than
+/// newlib's `unsigned long` on 32-bit targets.
+#[cfg(feature = "signal-rt")]
+#[allow(non_camel_case_types)]
+pub type sigset_t = u64;
+
+/// The handler is a [`SigInfoHandler`], rather than taking just the signal
+/// number (newlib's value - Linux uses 4)
+pub const SA_SIGINFO: c_int = 2;
+
+/// Don't block the signal while its handler runs
+pub const SA_NODEFER: c_int = 0x4000_0000;
+
+/// Reset the handler to `SIG_DFL` when the signal is delivered
+pub const SA_RESETHAND: c_int = 0x8000_0000_u32 as c_int;
+
+/// Tell `sigprocmask` to block exactly the given signals (newlib's value -
+/// Linux uses 2)
+pub const SIG_SETMASK: c_int = 0;
+
+/// Tell `sigprocmask` to block the given signals (newlib's value - Linux
+/// uses 0)
+pub const SIG_BLOCK: c_int = 1;
+
+/// Tell `sigprocmask` to unblock the given signals (newlib's value - Linux
+/// uses 1)
+pub const SIG_UNBLOCK: c_int = 2;
+
+/// What to do when a signal is delivered, as used by [`sigaction()`].
+///
+/// This has the same layout as newlib's (non-RTEMS) `struct sigaction`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
+pub struct sigaction {
+	/// `SIG_DFL`, `SIG_IGN` or a function pointer (a [`SigInfoHandler`], if
+	/// `sa_flags` has `SA_SIGINFO`). This is `sa_sigaction` too.
+	pub sa_handler: SignalHandler,
+	/// Signals to block while the handler runs
+	pub sa_mask: sigset_t,
+	/// `SA_SIGINFO`, `SA_NODEFER` and/or `SA_RESETHAND`
+	pub sa_flags: c_int,
+}
+
+/// The number of 32-bit words in an [`AtomicSigset`].
+const SIGSET_WORDS: usize = (NSIG - 1).div_ceil(32);
+
+/// A `sigset_t` which can be changed atomically, one 32-bit word at a time.
+///
+/// 32-bit atomics are lock-free on every target with atomic instructions,
+/// whereas 64-bit ones on a 32-bit target take a global lock, which an
+/// interrupt handler could deadlock on.
+struct AtomicSigset {
+	words: [AtomicU32; SIGSET_WORDS],
+}
+
+impl AtomicSigset {
+	const fn new() -> AtomicSigset {
+		#[allow(clippy::declare_interior_mutable_const)]
+		const EMPTY: AtomicU32 = AtomicU32::new(0);
+		AtomicSigset {
+			words: [EMPTY; SIGSET_WORDS],
+		}
+	}
+
+	/// Apply `op` to each word, with the matching word of `set`, returning
+	/// the set made of the words it returns.
+	fn apply(&self, set: sigset_t, op: impl Fn(&AtomicU32, u32) -> u32) -> sigset_t {
+		self.words
+			.iter()
+			.enumerate()
+			.fold(0, |result, (idx, word)| {
+				let shift = idx as u32 * 32;
+				let bits = set.checked_shr(shift).unwrap_or(0) as u32;
+				result
+					| sigset_t::from(op(word, bits))
+						.checked_shl(shift)
+						.unwrap_or(0)
+			})
+	}
+
+	fn load(&self, order: Ordering) -> sigset_t {
+		self.apply(0, |word, _| word.load(order))
+	}
+
+	fn store(&self, set: sigset_t, order: Ordering) {
+		self.apply(set, |word, bits| {
+			word.store(bits, order);
+			bits
+		});
+	}
+
+	fn swap(&self, set: sigset_t, order: Ordering) -> sigset_t {
+		self.apply(set, |word, bits| word.swap(bits, order))
+	}
+
+	fn fetch_or(&self, set: sigset_t, order: Ordering) -> sigset_t {
+		self.apply(set, |word, bits| word.fetch_or(bits, order))
+	}
+
+	fn fetch_and(&self, set: sigset_t, order: Ordering) -> sigset_t {
+		self.apply(set, |word, bits| word.fetch_and(bits, order))
+	}
+}
+
+/// An initialiser for our arrays of masks.
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_SET_ATOMIC: AtomicSigset = AtomicSigset::new();
+
+/// An initialiser for our array of flags.
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_FLAGS_ATOMIC: AtomicI32 = AtomicI32::new(0);
+
+/// The `sa_mask` for each signal.
+static SIGNAL_MASKS: [AtomicSigset; NSIG] = [EMPTY_SET_ATOMIC; NSIG];
+
+/// The `sa_flags` for each signal.
+static SIGNAL_FLAGS: [AtomicI32; NSIG] = [NO_FLAGS_ATOMIC; NSIG];
+
+/// The signals which are currently blocked.
+static BLOCKED: AtomicSigset = AtomicSigset::new();
+
+/// The signals which were raised while blocked, and not yet delivered.
+static PENDING: AtomicSigset = AtomicSigset::new();
+
+/// The signals which can be blocked - all but SIGKILL and SIGSTOP.
+const BLOCKABLE: sigset_t = !(sigbit(SIGKILL) | sigbit(SIGSTOP));
+
+/// Get the bit for a signal in a `sigset_t`.
+const fn sigbit(sig: c_int) -> sigset_t {
+	1 << (sig - 1)
+}
+
+/// Check a signal number, and get its index into our tables.
+fn index(sig: c_int) -> Option<usize> {
+	usize::try_from(sig)
+		.ok()
+		.filter(|&idx| idx != 0 && idx < NSIG)
+}
+
+/// A function to call when a signal's default action is to terminate.
 ///
-/// TODO: Support SIGSEGV, SIGILL, SIGFPE by using the `cortex-m-rt` or
-/// `riscv-rt` crate.
-const SIGNALS: [i32; 6] = [SIGTERM, SIGSEGV, SIGINT, SIGILL, SIGABRT, SIGFPE];
+/// It is given the signal number. It may shut down and never return, or
+/// start a graceful shutdown and return, in which case `raise` returns too.
+pub type TerminationHook = unsafe extern "C" fn(sig: c_int);
+
+/// The registered termination hook, or 0 for the default
+static TERMINATION_HOOK: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
+
+/// What happens to a signal whose handler is `SIG_DFL`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DefaultAction {
+	/// End the program (including those POSIX says should also dump core)
+	Terminate,
+	/// Nothing
+	Ignore,
+	/// Stop the program. We've nothing to stop, so this does nothing.
+	Stop,
+	/// Continue the program if stopped. This does nothing too.
+	Continue,
+}
+
+/// Get the POSIX default action for a signal.
+fn default_action(sig: c_int) -> DefaultAction {
+	match sig {
+		SIGCHLD | SIGURG | SIGWINCH => DefaultAction::Ignore,
+		SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+		SIGCONT => DefaultAction::Continue,
+		_ => DefaultAction::Terminate,
+	}
+}
+
+/// Check whether delivering a signal with this handler would do nothing.
+fn is_ignored(sig: c_int, handler: SignalHandler) -> bool {
+	handler == SIG_IGN || (handler == SIG_DFL && default_action(sig) == DefaultAction::Ignore)
+}
 
 /// An empty handler function that does nothing
 fn ignore_handler(_sig: i32) {}
 
-/// The default handler functions
+/// The default handler function
+///
+/// Carries out the signal's default action, calling the termination hook
+/// (or panicking, if there isn't one) for signals which terminate.
+fn default_handler(sig: i32) {
+	match default_action(sig) {
+		DefaultAction::Terminate => terminate(sig, TERMINATION_HOOK.load(Ordering::Acquire)),
+		DefaultAction::Ignore | DefaultAction::Stop | DefaultAction::Continue => {}
+	}
+}
+
+/// Terminate because of a signal, using the given termination hook (or 0 for
+/// the default).
+fn terminate(sig: c_int, hook: usize) {
+	if hook == 0 {
+		// TODO: This should call core::intrinsics::abort() but that's unstable.
+		panic!("Terminated by signal {}", sig);
+	}
+	unsafe {
+		let hook: TerminationHook = core::mem::transmute(hook);
+		hook(sig);
+	}
+}
+
+/// Set the function to call when a signal whose default action is to
+/// terminate is delivered to `SIG_DFL`, returning the previous one.
+///
+/// Passing NULL restores the default, which panics.
+#[cfg_attr(all(not(test), feature = "signal"), no_mangle)]
+pub extern "C" fn tinyrlibc_set_termination_hook(
+	hook: Option<TerminationHook>,
+) -> Option<TerminationHook> {
+	let new = hook.map_or(0, |hook| hook as usize);
+	let old = crate::hook::swap(&TERMINATION_HOOK, new);
+	// Safety: we only ever store valid hooks (or zero)
+	(old != 0).then(|| unsafe { core::mem::transmute::<usize, TerminationHook>(old) })
+}
+
+/// Deliver a signal which isn't blocked, running its handler.
 ///
-/// Performs a panic.
-fn default_handler(_sig: i32) {
-	// TODO: This should call core::intrinsics::abort() but that's unstable.
-	panic!("Aborted");
+/// `info` is given to `SA_SIGINFO` handlers; if it is `None`, the signal is
+/// reported as coming from `raise`.
+///
+/// Returns the number of signals delivered, including any pending signals
+/// which were delivered once the handler returned.
+fn deliver(sig: c_int, idx: usize, info: Option<&siginfo_t>) -> usize {
+	let handler = SIGNAL_HANDLERS[idx].load(Ordering::Acquire);
+	match handler {
+		SIG_DFL => {
+			default_handler(sig);
+		}
+		SIG_IGN => {
+			ignore_handler(sig);
+		}
+		_ => {
+			let flags = SIGNAL_FLAGS[idx].load(Ordering::Relaxed);
+			if flags & SA_RESETHAND != 0 {
+				SIGNAL_HANDLERS[idx].store(SIG_DFL, Ordering::Release);
+			}
+			// Block the signal itself (unless asked not to), and its mask,
+			// while the handler runs
+			let mut block = SIGNAL_MASKS[idx].load(Ordering::Relaxed);
+			if flags & (SA_NODEFER | SA_RESETHAND) == 0 {
+				block |= sigbit(sig);
+			}
+			let old_blocked = BLOCKED.fetch_or(block & BLOCKABLE, Ordering::AcqRel);
+			if flags & SA_SIGINFO != 0 {
+				let mut info = info.copied().unwrap_or(siginfo_t::user(sig));
+				unsafe {
+					let handler_fn: SigInfoHandler = core::mem::transmute(handler);
+					handler_fn(sig, &mut info, core::ptr::null_mut());
+				}
+			} else {
+				unsafe {
+					let handler_fn: unsafe extern "C" fn(c_int) = core::mem::transmute(handler);
+					handler_fn(sig);
+				}
+			}
+			BLOCKED.store(old_blocked, Ordering::Release);
+			return 1 + deliver_pending();
+		}
+	}
+	1
+}
+
+/// Deliver any pending signals which are no longer blocked, lowest first.
+///
+/// Returns the number of signals delivered.
+fn deliver_pending() -> usize {
+	let mut count = 0;
+	loop {
+		let ready = PENDING.load(Ordering::Acquire) & !BLOCKED.load(Ordering::Acquire);
+		if ready == 0 {
+			return count;
+		}
+		let sig = ready.trailing_zeros() as c_int + 1;
+		// Only deliver it if nobody else got there first
+		if PENDING.fetch_and(!sigbit(sig), Ordering::AcqRel) & sigbit(sig) != 0 {
+			count += deliver(sig, sig as usize, None);
+		}
+	}
 }
 
 /// Rust implementation of the C standard library's `signal` function.
 ///
+/// This is the same as calling [`sigaction()`] with an empty `sa_mask` and
+/// `SA_NODEFER`, so (as before `sigaction` was added) the signal is not
+/// blocked while its handler runs, and raising it again from the handler
+/// runs the handler again straight away.
+///
 /// Using `not(test)` ensures we don't replace the actual OS `signal` function
 /// when running tests!
 #[cfg_attr(all(not(test), feature = "signal"), no_mangle)]
-pub unsafe extern "C" fn signal(sig: i32, handler: SignalHandler) -> SignalHandler {
-	if !SIGNALS.contains(&sig) {
+pub unsafe extern "C" fn signal(sig: c_int, handler: SignalHandler) -> SignalHandler {
+	let act = sigaction {
+		sa_handler: handler,
+		sa_mask: 0,
+		sa_flags: SA_NODEFER,
+	};
+	let mut old = act;
+	if sigaction(sig, &act, &mut old) != 0 {
 		return SIG_ERR;
 	}
-	SIGNAL_HANDLERS[sig as usize].swap(handler, Ordering::Relaxed)
+	old.sa_handler
 }
 
-/// Rust implementation of the C standard library's `raise` function.
+/// Rust implementation of the POSIX `sigaction` function.
 ///
-/// Using `not(test)` ensures we don't replace the actual OS `raise` function
-/// when running tests!
+/// If `oact` is not NULL, the old action is stored there. If `act` is not
+/// NULL, it becomes the new action. Returns 0, or -1 with `errno` set to
+/// `EINVAL` if `sig` is invalid (or is SIGKILL or SIGSTOP and `act` is not
+/// NULL).
+///
+/// Using `not(test)` ensures we don't replace the actual OS `sigaction`
+/// function when running tests!
 #[cfg_attr(all(not(test), feature = "signal"), no_mangle)]
-pub extern "C" fn raise(sig: i32) -> i32 {
-	if !SIGNALS.contains(&sig) {
+pub unsafe extern "C" fn sigaction(
+	sig: c_int,
+	act: *const sigaction,
+	oact: *mut sigaction,
+) -> c_int {
+	let Some(idx) = index(sig) else {
+		set_errno(EINVAL);
+		return -1;
+	};
+	if !act.is_null() && BLOCKABLE & sigbit(sig) == 0 {
+		set_errno(EINVAL);
 		return -1;
 	}
-	let handler = SIGNAL_HANDLERS[sig as usize].load(Ordering::Relaxed);
-	match handler {
-		SIG_DFL => {
-			default_handler(sig);
-		}
-		SIG_IGN => {
-			ignore_handler(sig);
+	let old = sigaction {
+		sa_handler: SIGNAL_HANDLERS[idx].load(Ordering::Acquire),
+		sa_mask: SIGNAL_MASKS[idx].load(Ordering::Relaxed),
+		sa_flags: SIGNAL_FLAGS[idx].load(Ordering::Relaxed),
+	};
+	if let Some(act) = act.as_ref() {
+		SIGNAL_MASKS[idx].store(act.sa_mask, Ordering::Relaxed);
+		SIGNAL_FLAGS[idx].store(act.sa_flags, Ordering::Relaxed);
+		SIGNAL_HANDLERS[idx].store(act.sa_handler, Ordering::Release);
+		// Setting a pending signal to be ignored discards it
+		if is_ignored(sig, act.sa_handler) {
+			PENDING.fetch_and(!sigbit(sig), Ordering::AcqRel);
 		}
-		_ => unsafe {
-			let handler_fn: unsafe extern "C" fn(core::ffi::c_int) = core::mem::transmute(handler);
-			handler_fn(sig);
+	}
+	if let Some(oact) = oact.as_mut() {
+		*oact = old;
+	}
+	0
+}
+
+/// Rust implementation of the POSIX `sigprocmask` function.
+///
+/// Changes which signals are blocked, according to `how` (`SIG_BLOCK`,
+/// `SIG_UNBLOCK` or `SIG_SETMASK`), storing the old set in `oset` if it is
+/// not NULL. SIGKILL and SIGSTOP can't be blocked. Any pending signals which
+/// are unblocked are delivered before this returns.
+///
+/// Returns 0, or -1 with `errno` set to `EINVAL` if `how` is invalid.
+#[cfg_attr(all(not(test), feature = "signal"), no_mangle)]
+pub unsafe extern "C" fn sigprocmask(
+	how: c_int,
+	set: *const sigset_t,
+	oset: *mut sigset_t,
+) -> c_int {
+	let old = match set.as_ref() {
+		None => BLOCKED.load(Ordering::Acquire),
+		Some(&set) => match how {
+			SIG_BLOCK => BLOCKED.fetch_or(set & BLOCKABLE, Ordering::AcqRel),
+			SIG_UNBLOCK => BLOCKED.fetch_and(!set, Ordering::AcqRel),
+			SIG_SETMASK => BLOCKED.swap(set & BLOCKABLE, Ordering::AcqRel),
+			_ => {
+				set_errno(EINVAL);
+				return -1;
+			}
 		},
+	};
+	if let Some(oset) = oset.as_mut() {
+		*oset = old;
 	}
+	deliver_pending();
+	0
+}
+
+/// Rust implementation of the POSIX `sigpending` function.
+///
+/// Stores the signals which are blocked and waiting to be delivered in `set`.
+#[cfg_attr(all(not(test), feature = "signal"), no_mangle)]
+pub unsafe extern "C" fn sigpending(set: *mut sigset_t) -> c_int {
+	*set = PENDING.load(Ordering::Acquire);
 	0
 }
 
+/// Rust implementation of the POSIX `sigemptyset` function.
 #[cfg_attr(all(not(test), feature = "signal"), no_mangle)]
-pub extern "C" fn abort() {
-	raise(SIGABRT);
+pub unsafe extern "C" fn sigemptyset(set: *mut sigset_t) -> c_int {
+	*set = 0;
+	0
 }
 
-#[cfg(test)]
-mod tests {
-	use super::*;
+/// Rust implementation of the POSIX `sigfillset` function.
+#[cfg_attr(all(not(test), feature = "signal"), no_mangle)]
+pub unsafe extern "C" fn sigfillset(set: *mut sigset_t) -> c_int {
+	*set = sigset_t::MAX >> (sigset_t::BITS as usize - (NSIG - 1));
+	0
+}
 
-	struct State {
-		inner: std::sync::Mutex<()>,
+/// Rust implementation of the POSIX `sigaddset` function.
+///
+/// Returns 0, or -1 with `errno` set to `EINVAL` if `sig` is invalid.
+#[cfg_attr(all(not(test), feature = "signal"), no_mangle)]
+pub unsafe extern "C" fn sigaddset(set: *mut sigset_t, sig: c_int) -> c_int {
+	if index(sig).is_none() {
+		set_errno(EINVAL);
+		return -1;
 	}
+	*set |= sigbit(sig);
+	0
+}
 
-	impl State {
-		fn lock(&self) -> std::sync::MutexGuard<'_, ()> {
-			// Ensure we have exclusive access
-			let guard = self.inner.lock().unwrap();
-			// Reset the global signal handler list to defaults
-			for sig in SIGNAL_HANDLERS.iter() {
-				sig.store(SIG_DFL, Ordering::SeqCst);
-			}
-			guard
-		}
+/// Rust implementation of the POSIX `sigdelset` function.
+///
+/// Returns 0, or -1 with `errno` set to `EINVAL` if `sig` is invalid.
+#[cfg_attr(all(not(test), feature = "signal"), no_mangle)]
+pub unsafe extern "C" fn sigdelset(set: *mut sigset_t, sig: c_int) -> c_int {
+	if index(sig).is_none() {
+		set_errno(EINVAL);
+		return -1;
 	}
+	*set &= !sigbit(sig);
+	0
+}
 
-	/// Used to ensure we don't run multiple signal test concurrently, because
-	/// they share some global state.
-	///
-	/// If a test fails, the lock will be poisoned and all subsequent tests will
-	/// fail.
-	static TEST_LOCK: State = State {
-		inner: std::sync::Mutex::new(()),
+/// Rust implementation of the POSIX `sigismember` function.
+///
+/// Returns 1 if `sig` is in `set`, 0 if it isn't, or -1 with `errno` set to
+/// `EINVAL` if `sig` is invalid.
+#[cfg_attr(all(not(test), feature = "signal"), no_mangle)]
+pub unsafe extern "C" fn sigismember(set: *const sigset_t, sig: c_int) -> c_int {
+	if index(sig).is_none() {
+		set_errno(EINVAL);
+		return -1;
+	}
+	(*set & sigbit(sig) != 0) as c_int
+}
+
+/// Rust implementation of the C standard library's `raise` function.
+///
+/// If the signal is blocked, it becomes pending, and is delivered when it is
+/// unblocked.
+///
+/// Using `not(test)` ensures we don't replace the actual OS `raise` function
+/// when running tests!
+#[cfg_attr(all(not(test), feature = "signal"), no_mangle)]
+pub extern "C" fn raise(sig: c_int) -> c_int {
+	let Some(idx) = index(sig) else {
+		return -1;
+	};
+	if BLOCKED.load(Ordering::Acquire) & sigbit(sig) != 0 {
+		PENDING.fetch_or(sigbit(sig), Ordering::AcqRel);
+		return 0;
+	}
+	deliver(sig, idx, None);
+	0
+}
+
+/// Make a signal pending, without running its handler.
+///
+/// This is safe to call from an interrupt handler: it only sets a bit in the
+/// pending set, using a 32-bit atomic operation. That is lock-free on
+/// targets with atomic read-modify-write instructions (such as thumbv7em and
+/// riscv32imac); on those without (such as thumbv6m), enable `signal-cs` and
+/// provide a `critical-section` implementation. The handler runs later, from
+/// [`tinyrlibc_signal_dispatch`] (or whenever pending signals are next
+/// delivered, such as after `raise` or `sigprocmask`).
+///
+/// A signal which is posted again before it is delivered is only delivered
+/// once. Posting a signal which would be ignored does nothing.
+///
+/// Returns 0, or -1 if `sig` is invalid. `errno` is not set, as it isn't
+/// safe to touch from an interrupt handler.
+#[cfg_attr(all(not(test), feature = "signal"), no_mangle)]
+pub extern "C" fn tinyrlibc_signal_post(sig: c_int) -> c_int {
+	let Some(idx) = index(sig) else {
+		return -1;
 	};
+	if !is_ignored(sig, SIGNAL_HANDLERS[idx].load(Ordering::Acquire)) {
+		PENDING.fetch_or(sigbit(sig), Ordering::AcqRel);
+	}
+	0
+}
+
+/// Deliver the pending signals which aren't blocked, lowest first.
+///
+/// Call this from the main loop, or from an RTOS task, to run the handlers
+/// for signals posted with [`tinyrlibc_signal_post`]. Returns the number of
+/// signals delivered.
+#[cfg_attr(all(not(test), feature = "signal"), no_mangle)]
+pub extern "C" fn tinyrlibc_signal_dispatch() -> c_int {
+	c_int::try_from(deliver_pending()).unwrap_or(c_int::MAX)
+}
+
+/// Deliver a signal for a CPU fault, such as SIGSEGV from a memory fault
+/// handler or SIGILL from an undefined instruction trap.
+///
+/// This runs the signal's handler straight away, giving `SA_SIGINFO`
+/// handlers `info` (with `si_signo` set to `sig`). If `info` is NULL, the
+/// handler gets an `si_code` of `SI_KERNEL`. As on Linux, if the signal is
+/// blocked or ignored, its default action is carried out instead, which for
+/// the fault signals is to call the termination hook.
+///
+/// If the handler returns, this returns 0, and it is up to the fault handler
+/// what to do next - returning from the fault will usually retry the
+/// instruction that faulted. Returns -1 if `sig` is invalid.
+///
+/// # Safety
+///
+/// `info` must be NULL or valid for reads.
+#[cfg_attr(all(not(test), feature = "signal"), no_mangle)]
+pub unsafe extern "C" fn tinyrlibc_deliver_fault(sig: c_int, info: *const siginfo_t) -> c_int {
+	let Some(idx) = index(sig) else {
+		return -1;
+	};
+	let mut info = info.as_ref().copied().unwrap_or(siginfo_t {
+		si_code: SI_KERNEL,
+		..siginfo_t::user(sig)
+	});
+	info.si_signo = sig;
+	let handler = SIGNAL_HANDLERS[idx].load(Ordering::Acquire);
+	if handler == SIG_IGN || BLOCKED.load(Ordering::Acquire) & sigbit(sig) != 0 {
+		default_handler(sig);
+	} else {
+		deliver(sig, idx, Some(&info));
+	}
+	0
+}
+
+/// Put every handler, mask and flag back to the default, and clear the
+/// blocked and pending sets, between tests.
+#[cfg(test)]
+pub(crate) fn reset() {
+	for sig in SIGNAL_HANDLERS.iter() {
+		sig.store(SIG_DFL, Ordering::SeqCst);
+	}
+	for mask in SIGNAL_MASKS.iter() {
+		mask.store(0, Ordering::SeqCst);
+	}
+	for flags in SIGNAL_FLAGS.iter() {
+		flags.store(0, Ordering::SeqCst);
+	}
+	BLOCKED.store(0, Ordering::SeqCst);
+	TERMINATION_HOOK.store(0, Ordering::SeqCst);
+	PENDING.store(0, Ordering::SeqCst);
+}
+
+/// Raise `SIGABRT` for `abort`, then put back `SIG_DFL`.
+///
+/// `abort` can't be blocked or ignored, and its default action is left to
+/// `abort` itself, so only a handler function is run here.
+pub(crate) fn raise_abort() {
+	let idx = SIGABRT as usize;
+	BLOCKED.fetch_and(!sigbit(SIGABRT), Ordering::AcqRel);
+	PENDING.fetch_and(!sigbit(SIGABRT), Ordering::AcqRel);
+	let handler = SIGNAL_HANDLERS[idx].load(Ordering::Acquire);
+	if handler != SIG_DFL && handler != SIG_IGN {
+		deliver(SIGABRT, idx, None);
+	}
+	SIGNAL_MASKS[idx].store(0, Ordering::Relaxed);
+	SIGNAL_FLAGS[idx].store(0, Ordering::Relaxed);
+	SIGNAL_HANDLERS[idx].store(SIG_DFL, Ordering::Release);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
 
 	#[test]
 	fn test_signal() {
-		let _guard = TEST_LOCK.lock();
+		let _guard = crate::test_lock();
 		static COUNT: AtomicUsize = AtomicUsize::new(0);
 		extern "C" fn count_handler(_sig: i32) {
 			COUNT.fetch_add(1, Ordering::Relaxed);
@@ -166,7 +805,7 @@ mod tests {
 
 	#[test]
 	fn test_abort() {
-		let _guard = TEST_LOCK.lock();
+		let _guard = crate::test_lock();
 		static COUNT: AtomicUsize = AtomicUsize::new(0);
 		extern "C" fn count_handler(_sig: i32) {
 			COUNT.fetch_add(1, Ordering::Relaxed);
@@ -174,22 +813,30 @@ mod tests {
 		let count_handler_ptr = count_handler as *const fn(i32) as usize;
 		let old_handler = unsafe { signal(SIGABRT, count_handler_ptr) };
 		assert_eq!(old_handler, SIG_DFL);
-		abort();
-		let old_handler = unsafe { signal(SIGABRT, SIG_DFL) };
+		// `abort` runs the handler even if SIGABRT is blocked, then puts
+		// back `SIG_DFL`
+		let set = sigbit(SIGABRT);
+		unsafe { sigprocmask(SIG_BLOCK, &set, core::ptr::null_mut()) };
+		raise_abort();
+		let old_handler = unsafe { signal(SIGABRT, SIG_IGN) };
+		assert_eq!(COUNT.load(Ordering::Relaxed), 1);
+		assert_eq!(old_handler, SIG_DFL);
+		// Ignoring it doesn't help either, but then there's nothing to run
+		raise_abort();
+		assert_eq!(unsafe { signal(SIGABRT, SIG_DFL) }, SIG_DFL);
 		assert_eq!(COUNT.load(Ordering::Relaxed), 1);
-		assert_eq!(old_handler, count_handler_ptr);
 	}
 
 	#[test]
 	fn test_signal_error() {
-		let _guard = TEST_LOCK.lock();
+		let _guard = crate::test_lock();
 		let err = unsafe { signal(1000, SIG_DFL) };
 		assert_eq!(err, SIG_ERR);
 	}
 
 	#[test]
 	fn test_ignore() {
-		let _guard = TEST_LOCK.lock();
+		let _guard = crate::test_lock();
 		let old_handler = unsafe { signal(SIGTERM, SIG_IGN) };
 		assert_eq!(old_handler, SIG_DFL);
 		// Shouldn't cause a panic
@@ -201,5 +848,462 @@ mod tests {
 	#[test]
 	fn test_raise_error() {
 		assert!(raise(1000) == -1);
+		assert!(raise(0) == -1);
+		assert!(raise(-1) == -1);
+		assert!(raise(NSIG as c_int) == -1);
+	}
+
+	#[test]
+	fn test_posix_signals() {
+		let _guard = crate::test_lock();
+		static COUNT: AtomicUsize = AtomicUsize::new(0);
+		extern "C" fn count_handler(sig: c_int) {
+			COUNT.fetch_add(sig as usize, Ordering::Relaxed);
+		}
+		let count_handler_ptr = count_handler as *const fn(c_int) as usize;
+		for sig in [SIGUSR1, SIGUSR2, SIGALRM, SIGCHLD, SIGPIPE, SIGSYS] {
+			assert_eq!(unsafe { signal(sig, count_handler_ptr) }, SIG_DFL);
+			assert_eq!(raise(sig), 0);
+		}
+		assert_eq!(
+			COUNT.load(Ordering::Relaxed),
+			(SIGUSR1 + SIGUSR2 + SIGALRM + SIGCHLD + SIGPIPE + SIGSYS) as usize
+		);
+	}
+
+	#[test]
+	fn test_uncatchable() {
+		let _guard = crate::test_lock();
+		for sig in [SIGKILL, SIGSTOP] {
+			assert_eq!(unsafe { signal(sig, SIG_IGN) }, SIG_ERR);
+		}
+		assert_eq!(unsafe { signal(0, SIG_IGN) }, SIG_ERR);
+		assert_eq!(unsafe { signal(NSIG as c_int, SIG_IGN) }, SIG_ERR);
+	}
+
+	/// Records which signals were delivered, in order
+	static DELIVERED: std::sync::Mutex<Vec<c_int>> = std::sync::Mutex::new(Vec::new());
+
+	extern "C" fn record_handler(sig: c_int) {
+		DELIVERED.lock().unwrap().push(sig);
+	}
+
+	fn delivered() -> Vec<c_int> {
+		core::mem::take(&mut *DELIVERED.lock().unwrap())
+	}
+
+	fn action(handler: SignalHandler, mask: sigset_t, flags: c_int) -> sigaction {
+		sigaction {
+			sa_handler: handler,
+			sa_mask: mask,
+			sa_flags: flags,
+		}
+	}
+
+	#[test]
+	fn test_sigaction() {
+		let _guard = crate::test_lock();
+		let act = action(
+			record_handler as *const fn(c_int) as usize,
+			sigbit(SIGUSR2),
+			0,
+		);
+		let mut old = action(SIG_ERR, 0, 0);
+		assert_eq!(unsafe { sigaction(SIGUSR1, &act, &mut old) }, 0);
+		assert_eq!(old, action(SIG_DFL, 0, 0));
+		assert_eq!(
+			unsafe { sigaction(SIGUSR1, core::ptr::null(), &mut old) },
+			0
+		);
+		assert_eq!(old, act);
+		raise(SIGUSR1);
+		assert_eq!(delivered(), [SIGUSR1]);
+
+		// signal() reports (and replaces) the handler set by sigaction
+		assert_eq!(
+			unsafe { signal(SIGUSR1, SIG_IGN) },
+			record_handler as *const fn(c_int) as usize
+		);
+
+		crate::errno::set_errno(0);
+		assert_eq!(unsafe { sigaction(SIGKILL, &act, &mut old) }, -1);
+		assert_eq!(crate::errno::errno(), EINVAL);
+		assert_eq!(
+			unsafe { sigaction(SIGKILL, core::ptr::null(), &mut old) },
+			0
+		);
+		assert_eq!(unsafe { sigaction(0, &act, &mut old) }, -1);
+	}
+
+	#[test]
+	fn test_signal_does_not_block() {
+		let _guard = crate::test_lock();
+		extern "C" fn nested_handler(sig: c_int) {
+			let first = {
+				let mut delivered = DELIVERED.lock().unwrap();
+				delivered.push(sig);
+				delivered.len() == 1
+			};
+			if first {
+				raise(sig);
+				DELIVERED.lock().unwrap().push(0);
+			}
+		}
+		let handler = nested_handler as *const fn(c_int) as usize;
+		unsafe { signal(SIGUSR1, handler) };
+		raise(SIGUSR1);
+		// The second delivery runs inside the first
+		assert_eq!(delivered(), [SIGUSR1, SIGUSR1, 0]);
+		let mut old = action(SIG_ERR, 0, 0);
+		unsafe { sigaction(SIGUSR1, core::ptr::null(), &mut old) };
+		assert_eq!(old, action(handler, 0, SA_NODEFER));
+	}
+
+	#[test]
+	fn test_sigaction_resethand() {
+		let _guard = crate::test_lock();
+		let act = action(record_handler as *const fn(c_int) as usize, 0, SA_RESETHAND);
+		assert_eq!(
+			unsafe { sigaction(SIGUSR1, &act, core::ptr::null_mut()) },
+			0
+		);
+		raise(SIGUSR1);
+		assert_eq!(delivered(), [SIGUSR1]);
+		assert_eq!(unsafe { signal(SIGUSR1, SIG_IGN) }, SIG_DFL);
+	}
+
+	#[test]
+	fn test_handler_blocks_signals() {
+		let _guard = crate::test_lock();
+		extern "C" fn reraise_handler(sig: c_int) {
+			let first = {
+				let mut delivered = DELIVERED.lock().unwrap();
+				delivered.push(sig);
+				delivered.len() == 1
+			};
+			if first {
+				raise(SIGUSR2);
+				raise(SIGUSR1);
+			}
+		}
+		let record = action(record_handler as *const fn(c_int) as usize, 0, 0);
+		unsafe { sigaction(SIGUSR2, &record, core::ptr::null_mut()) };
+
+		// Without SA_NODEFER, the handler's own signal and its mask are
+		// blocked until it returns, then delivered lowest first
+		let act = action(
+			reraise_handler as *const fn(c_int) as usize,
+			sigbit(SIGUSR2),
+			0,
+		);
+		unsafe { sigaction(SIGUSR1, &act, core::ptr::null_mut()) };
+		raise(SIGUSR1);
+		assert_eq!(delivered(), [SIGUSR1, SIGUSR1, SIGUSR2]);
+
+		// With SA_NODEFER (and no mask), they are delivered straight away
+		let act = action(reraise_handler as *const fn(c_int) as usize, 0, SA_NODEFER);
+		unsafe { sigaction(SIGUSR1, &act, core::ptr::null_mut()) };
+		raise(SIGUSR1);
+		assert_eq!(delivered(), [SIGUSR1, SIGUSR2, SIGUSR1]);
+	}
+
+	#[test]
+	fn test_sigprocmask() {
+		let _guard = crate::test_lock();
+		let act = action(record_handler as *const fn(c_int) as usize, 0, 0);
+		for sig in [SIGUSR1, SIGUSR2, SIGTERM] {
+			unsafe { sigaction(sig, &act, core::ptr::null_mut()) };
+		}
+		let mut set: sigset_t = 0;
+		let mut old: sigset_t = 0;
+		unsafe {
+			sigemptyset(&mut set);
+			sigaddset(&mut set, SIGUSR1);
+			sigaddset(&mut set, SIGUSR2);
+			sigaddset(&mut set, SIGKILL);
+			assert_eq!(sigprocmask(SIG_BLOCK, &set, &mut old), 0);
+		}
+		assert_eq!(old, 0);
+
+		raise(SIGUSR2);
+		raise(SIGUSR1);
+		raise(SIGUSR1);
+		raise(SIGTERM);
+		assert_eq!(delivered(), [SIGTERM]);
+		let mut pending: sigset_t = 0;
+		unsafe { sigpending(&mut pending) };
+		assert_eq!(pending, sigbit(SIGUSR1) | sigbit(SIGUSR2));
+
+		// SIGKILL can't be blocked
+		unsafe { sigprocmask(SIG_SETMASK, core::ptr::null(), &mut old) };
+		assert_eq!(old, sigbit(SIGUSR1) | sigbit(SIGUSR2));
+
+		// Pending signals are delivered once (however many times they were
+		// raised), lowest first
+		unsafe { sigprocmask(SIG_UNBLOCK, &set, core::ptr::null_mut()) };
+		assert_eq!(delivered(), [SIGUSR1, SIGUSR2]);
+
+		// Ignoring a pending signal discards it
+		unsafe { sigprocmask(SIG_SETMASK, &set, core::ptr::null_mut()) };
+		raise(SIGUSR1);
+		unsafe {
+			signal(SIGUSR1, SIG_IGN);
+			sigpending(&mut pending);
+		}
+		assert_eq!(pending, 0);
+
+		assert_eq!(unsafe { sigprocmask(99, &set, core::ptr::null_mut()) }, -1);
+	}
+
+	#[test]
+	fn test_post_and_dispatch() {
+		let _guard = crate::test_lock();
+		let act = action(record_handler as *const fn(c_int) as usize, 0, 0);
+		for sig in [SIGHUP, SIGUSR1, SIGTERM] {
+			unsafe { sigaction(sig, &act, core::ptr::null_mut()) };
+		}
+		unsafe { signal(SIGPIPE, SIG_IGN) };
+		let set = sigbit(SIGUSR1);
+		unsafe { sigprocmask(SIG_BLOCK, &set, core::ptr::null_mut()) };
+
+		// Posting doesn't run anything
+		assert_eq!(tinyrlibc_signal_post(SIGTERM), 0);
+		assert_eq!(tinyrlibc_signal_post(SIGUSR1), 0);
+		assert_eq!(tinyrlibc_signal_post(SIGTERM), 0);
+		assert_eq!(tinyrlibc_signal_post(SIGHUP), 0);
+		assert_eq!(tinyrlibc_signal_post(SIGPIPE), 0);
+		assert_eq!(tinyrlibc_signal_post(0), -1);
+		assert_eq!(tinyrlibc_signal_post(NSIG as c_int), -1);
+		assert_eq!(delivered(), []);
+		let mut pending: sigset_t = 0;
+		unsafe { sigpending(&mut pending) };
+		assert_eq!(pending, sigbit(SIGHUP) | sigbit(SIGUSR1) | sigbit(SIGTERM));
+
+		// Blocked signals stay pending
+		assert_eq!(tinyrlibc_signal_dispatch(), 2);
+		assert_eq!(delivered(), [SIGHUP, SIGTERM]);
+		assert_eq!(tinyrlibc_signal_dispatch(), 0);
+
+		unsafe { sigprocmask(SIG_UNBLOCK, &set, core::ptr::null_mut()) };
+		assert_eq!(delivered(), [SIGUSR1]);
+		unsafe { sigpending(&mut pending) };
+		assert_eq!(pending, 0);
+	}
+
+	#[test]
+	fn test_default_actions() {
+		for sig in [SIGCHLD, SIGURG, SIGWINCH] {
+			assert_eq!(default_action(sig), DefaultAction::Ignore);
+		}
+		for sig in [SIGSTOP, SIGTSTP, SIGTTIN, SIGTTOU] {
+			assert_eq!(default_action(sig), DefaultAction::Stop);
+		}
+		assert_eq!(default_action(SIGCONT), DefaultAction::Continue);
+		for sig in [SIGHUP, SIGINT, SIGKILL, SIGTERM, SIGSEGV, SIGABRT, SIGUSR1] {
+			assert_eq!(default_action(sig), DefaultAction::Terminate);
+		}
+	}
+
+	#[test]
+	fn test_termination_hook() {
+		let _guard = crate::test_lock();
+		assert!(tinyrlibc_set_termination_hook(Some(record_handler)).is_none());
+		for sig in [SIGCHLD, SIGTSTP, SIGCONT, SIGTERM, SIGURG, SIGINT] {
+			assert_eq!(raise(sig), 0);
+		}
+		assert_eq!(delivered(), [SIGTERM, SIGINT]);
+
+		// Signals ignored by default are discarded rather than left pending
+		let set = sigbit(SIGCHLD) | sigbit(SIGHUP);
+		unsafe { sigprocmask(SIG_BLOCK, &set, core::ptr::null_mut()) };
+		assert_eq!(tinyrlibc_signal_post(SIGCHLD), 0);
+		assert_eq!(tinyrlibc_signal_post(SIGHUP), 0);
+		let mut pending: sigset_t = 0;
+		unsafe { sigpending(&mut pending) };
+		assert_eq!(pending, sigbit(SIGHUP));
+		unsafe { sigprocmask(SIG_UNBLOCK, &set, core::ptr::null_mut()) };
+		assert_eq!(delivered(), [SIGHUP]);
+
+		let old = tinyrlibc_set_termination_hook(None);
+		assert_eq!(
+			old.map(|hook| hook as usize),
+			Some(record_handler as *const fn(c_int) as usize)
+		);
+	}
+
+	// `raise` can't unwind, so the default is tested directly
+	#[test]
+	#[should_panic(expected = "Terminated by signal 15")]
+	fn test_terminate_panics() {
+		terminate(SIGTERM, 0);
+	}
+
+	#[test]
+	fn test_siginfo() {
+		let _guard = crate::test_lock();
+		/// The signal number, and the `si_signo`, `si_code` and `si_addr` given
+		static INFO: std::sync::Mutex<Vec<[usize; 4]>> = std::sync::Mutex::new(Vec::new());
+		extern "C" fn info_handler(sig: c_int, info: *mut siginfo_t, context: *mut c_void) {
+			assert!(context.is_null());
+			let info = unsafe { *info };
+			INFO.lock().unwrap().push([
+				sig as usize,
+				info.si_signo as usize,
+				info.si_code as usize,
+				info.si_addr as usize,
+			]);
+		}
+		let act = action(info_handler as *const fn(c_int) as usize, 0, SA_SIGINFO);
+		for sig in [SIGSEGV, SIGILL, SIGUSR1] {
+			unsafe { sigaction(sig, &act, core::ptr::null_mut()) };
+		}
+
+		raise(SIGUSR1);
+		let fault = siginfo_t {
+			si_code: SEGV_ACCERR,
+			si_addr: 0x2000_0000 as *mut c_void,
+			..siginfo_t::user(0)
+		};
+		assert_eq!(unsafe { tinyrlibc_deliver_fault(SIGSEGV, &fault) }, 0);
+		assert_eq!(
+			unsafe { tinyrlibc_deliver_fault(SIGILL, core::ptr::null()) },
+			0
+		);
+		assert_eq!(unsafe { tinyrlibc_deliver_fault(0, &fault) }, -1);
+		let (usr1, segv, ill) = (SIGUSR1 as usize, SIGSEGV as usize, SIGILL as usize);
+		assert_eq!(
+			*INFO.lock().unwrap(),
+			[
+				[usr1, usr1, SI_USER as usize, 0],
+				[segv, segv, SEGV_ACCERR as usize, 0x2000_0000],
+				[ill, ill, SI_KERNEL as usize, 0],
+			]
+		);
+	}
+
+	#[test]
+	fn test_blocked_fault_terminates() {
+		let _guard = crate::test_lock();
+		tinyrlibc_set_termination_hook(Some(record_handler));
+		let act = action(record_handler as *const fn(c_int) as usize, 0, 0);
+		unsafe {
+			sigaction(SIGFPE, &act, core::ptr::null_mut());
+			signal(SIGBUS, SIG_IGN);
+		}
+		let set = sigbit(SIGFPE);
+		unsafe { sigprocmask(SIG_BLOCK, &set, core::ptr::null_mut()) };
+
+		// Each goes to the termination hook, rather than being left pending
+		// or ignored
+		assert_eq!(
+			unsafe { tinyrlibc_deliver_fault(SIGFPE, core::ptr::null()) },
+			0
+		);
+		assert_eq!(
+			unsafe { tinyrlibc_deliver_fault(SIGBUS, core::ptr::null()) },
+			0
+		);
+		assert_eq!(delivered(), [SIGFPE, SIGBUS]);
+		let mut pending: sigset_t = 0;
+		unsafe { sigpending(&mut pending) };
+		assert_eq!(pending, 0);
+	}
+
+	#[test]
+	#[cfg(all(feature = "setjmp", target_arch = "x86_64", target_os = "linux"))]
+	fn test_siglongjmp_restores_mask() {
+		use crate::setjmp::{sigjmp_buf, siglongjmp, tinyrlibc_test_sigsetjmp_and_jump};
+		/// Block a different signal, then jump back to `env`.
+		extern "C" fn jump(env: *mut sigjmp_buf, val: c_int) {
+			let other = sigbit(SIGUSR2);
+			unsafe {
+				sigprocmask(SIG_SETMASK, &other, core::ptr::null_mut());
+				siglongjmp(env, val);
+			}
+		}
+		let _guard = crate::test_lock();
+		let set = sigbit(SIGUSR1);
+		unsafe { sigprocmask(SIG_SETMASK, &set, core::ptr::null_mut()) };
+		let mut env: sigjmp_buf = unsafe { core::mem::zeroed() };
+		assert_eq!(
+			unsafe { tinyrlibc_test_sigsetjmp_and_jump(&mut env, 1, jump, 1) },
+			1
+		);
+		assert_eq!(env.__mask_was_saved, 1);
+		let mut now: sigset_t = 0;
+		unsafe { sigprocmask(SIG_BLOCK, core::ptr::null(), &mut now) };
+		assert_eq!(now, set);
+	}
+
+	#[test]
+	fn test_sigset() {
+		let mut set: sigset_t = 0;
+		unsafe {
+			assert_eq!(sigfillset(&mut set), 0);
+			for sig in 1..NSIG as c_int {
+				assert_eq!(sigismember(&set, sig), 1);
+			}
+			assert_eq!(sigdelset(&mut set, SIGINT), 0);
+			assert_eq!(sigismember(&set, SIGINT), 0);
+			assert_eq!(sigemptyset(&mut set), 0);
+			assert_eq!(set, 0);
+			assert_eq!(sigaddset(&mut set, SIGSYS), 0);
+			assert_eq!(sigismember(&set, SIGSYS), 1);
+			assert_eq!(sigismember(&set, SIGHUP), 0);
+			assert_eq!(sigaddset(&mut set, 0), -1);
+			assert_eq!(sigaddset(&mut set, NSIG as c_int), -1);
+			assert_eq!(sigdelset(&mut set, -1), -1);
+			assert_eq!(sigismember(&set, 1000), -1);
+		}
+		assert_eq!(set, sigbit(SIGSYS));
+	}
+
+	#[test]
+	fn test_newlib_numbers() {
+		assert_eq!(
+			[SIGBUS, SIGSYS, SIGURG, SIGSTOP, SIGCHLD, SIGUSR1, SIGUSR2],
+			[10, 12, 16, 17, 20, 30, 31]
+		);
+		assert_eq!(SIGPWR, SIGLOST);
+		assert_eq!(SA_SIGINFO, 2);
+		assert_eq!([SIG_SETMASK, SIG_BLOCK, SIG_UNBLOCK], [0, 1, 2]);
+		assert_eq!(SI_USER, 1);
+	}
+
+	#[test]
+	#[cfg(not(feature = "signal-rt"))]
+	fn test_newlib_layout() {
+		use core::mem::{offset_of, size_of};
+		assert_eq!(size_of::<sigset_t>(), size_of::<core::ffi::c_ulong>());
+		assert_eq!(offset_of!(sigaction, sa_mask), size_of::<usize>());
+		assert_eq!(
+			offset_of!(sigaction, sa_flags),
+			size_of::<usize>() + size_of::<sigset_t>()
+		);
+		assert_eq!(offset_of!(siginfo_t, si_code), size_of::<c_int>());
+		assert_eq!(offset_of!(siginfo_t, si_value), size_of::<usize>());
+	}
+
+	#[test]
+	#[cfg(feature = "signal-rt")]
+	fn test_realtime_signals() {
+		let _guard = crate::test_lock();
+		assert_eq!(SIGRTMAX, 64);
+		for sig in SIGRTMIN..=SIGRTMAX {
+			assert_eq!(unsafe { signal(sig, SIG_IGN) }, SIG_DFL);
+			assert_eq!(raise(sig), 0);
+		}
+
+		// They pend in the second word of the set
+		let act = action(record_handler as *const fn(c_int) as usize, 0, 0);
+		unsafe { sigaction(SIGRTMAX, &act, core::ptr::null_mut()) };
+		let set = sigbit(SIGRTMAX) | sigbit(SIGHUP);
+		unsafe { sigprocmask(SIG_BLOCK, &set, core::ptr::null_mut()) };
+		assert_eq!(tinyrlibc_signal_post(SIGRTMAX), 0);
+		let mut pending: sigset_t = 0;
+		unsafe { sigpending(&mut pending) };
+		assert_eq!(pending, sigbit(SIGRTMAX));
+		unsafe { sigprocmask(SIG_UNBLOCK, &set, core::ptr::null_mut()) };
+		assert_eq!(delivered(), [SIGRTMAX]);
 	}
 }
@@ -86,29 +86,25 @@ mod test {
 
 	#[test]
 	fn int_min() {
-		asprintf(
-			"%d",
-			&format!("{}", c_int::min_value()),
-			|buf, len, fmt| unsafe { snprintf(buf, len, fmt, c_int::min_value()) },
-		);
+		asprintf("%d", &format!("{}", c_int::MIN), |buf, len, fmt| unsafe {
+			snprintf(buf, len, fmt, c_int::MIN)
+		});
 		asprintf(
 			"%lld",
-			&format!("{}", c_longlong::min_value()),
-			|buf, len, fmt| unsafe { snprintf(buf, len, fmt, c_longlong::min_value()) },
+			&format!("{}", c_longlong::MIN),
+			|buf, len, fmt| unsafe { snprintf(buf, len, fmt, c_longlong::MIN) },
 		);
 	}
 
 	#[test]
 	fn int_max() {
-		asprintf(
-			"%d",
-			&format!("{}", c_int::max_value()),
-			|buf, len, fmt| unsafe { snprintf(buf, len, fmt, c_int::max_value()) },
-		);
+		asprintf("%d", &format!("{}", c_int::MAX), |buf, len, fmt| unsafe {
+			snprintf(buf, len, fmt, c_int::MAX)
+		});
 		asprintf(
 			"%lld",
-			&format!("{}", c_longlong::max_value()),
-			|buf, len, fmt| unsafe { snprintf(buf, len, fmt, c_longlong::max_value()) },
+			&format!("{}", c_longlong::MAX),
+			|buf, len, fmt| unsafe { snprintf(buf, len, fmt, c_longlong::MAX) },
 		);
 	}
 
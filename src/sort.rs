@@ -0,0 +1,100 @@
+//! Safe Rust interface to the introsort used by `qsort`.
+//!
+//! This lets Rust code sort buffers of fixed-width records (for example,
+//! arrays owned by C code) with the same implementation that C code gets
+//! from `qsort`, without going through an `extern "C"` comparator.
+//!
+//! Licensed under the Blue Oak Model Licence 1.0.0
+
+use core::cmp::Ordering;
+use core::ffi::{c_int, c_void};
+
+use crate::qsort::introsort;
+
+/// Sort `data`, treating it as a sequence of records each `width` bytes long.
+///
+/// `compare` is given two records and returns their relative order. The sort
+/// is not stable.
+///
+/// # Panics
+///
+/// Panics if `width` is zero, or if the length of `data` is not a multiple of
+/// `width`.
+pub fn sort_bytes<F>(data: &mut [u8], width: usize, mut compare: F)
+where
+	F: FnMut(&[u8], &[u8]) -> Ordering,
+{
+	assert!(width != 0, "record width must not be zero");
+	assert!(
+		data.len() % width == 0,
+		"slice length must be a multiple of the record width"
+	);
+	let nel = data.len() / width;
+	introsort(
+		data.as_mut_ptr().cast(),
+		nel,
+		width,
+		&mut |a: *const c_void, b: *const c_void| {
+			// Safety: the sort only passes us pointers to whole records
+			// within `data`, and doesn't write to them while we hold these.
+			let a = unsafe { core::slice::from_raw_parts(a.cast::<u8>(), width) };
+			let b = unsafe { core::slice::from_raw_parts(b.cast::<u8>(), width) };
+			compare(a, b) as c_int
+		},
+	);
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	/// Pack some `u32` keys, each followed by a tag byte, into 5-byte records.
+	fn records(keys: &[u32]) -> Vec<u8> {
+		keys.iter()
+			.enumerate()
+			.flat_map(|(idx, key)| key.to_le_bytes().into_iter().chain([idx as u8]))
+			.collect()
+	}
+
+	fn key(record: &[u8]) -> u32 {
+		u32::from_le_bytes(record[..4].try_into().unwrap())
+	}
+
+	#[test]
+	fn sorts_records() {
+		let keys: Vec<u32> = (0..100).map(|x| (x * 37) % 100).collect();
+		let mut data = records(&keys);
+		sort_bytes(&mut data, 5, |a, b| key(a).cmp(&key(b)));
+		let sorted: Vec<u32> = data.chunks(5).map(key).collect();
+		assert_eq!(sorted, (0..100).collect::<Vec<_>>());
+		// The tag bytes moved with their keys
+		for record in data.chunks(5) {
+			assert_eq!(keys[record[4] as usize], key(record));
+		}
+	}
+
+	#[test]
+	fn sorts_descending() {
+		let mut data = records(&[3, 1, 2]);
+		sort_bytes(&mut data, 5, |a, b| key(b).cmp(&key(a)));
+		let sorted: Vec<u32> = data.chunks(5).map(key).collect();
+		assert_eq!(sorted, [3, 2, 1]);
+	}
+
+	#[test]
+	fn empty() {
+		sort_bytes(&mut [], 4, |_, _| unreachable!());
+	}
+
+	#[test]
+	#[should_panic]
+	fn partial_record() {
+		sort_bytes(&mut [0u8; 7], 4, |a, b| a.cmp(b));
+	}
+
+	#[test]
+	#[should_panic]
+	fn zero_width() {
+		sort_bytes(&mut [0u8; 4], 0, |a, b| a.cmp(b));
+	}
+}
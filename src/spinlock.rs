@@ -0,0 +1,45 @@
+//! A spin lock, for global state such as the heap.
+//!
+//! Code holding the lock must not be interrupted by code which takes the same
+//! lock, or it will spin forever, so these locks must not be taken from
+//! interrupt handlers.
+//!
+//! Licensed under the Blue Oak Model Licence 1.0.0
+
+use core::cell::UnsafeCell;
+
+use portable_atomic::{AtomicBool, Ordering};
+
+/// A `T` behind a spin lock.
+pub(crate) struct SpinLock<T> {
+	locked: AtomicBool,
+	value: UnsafeCell<T>,
+}
+
+// Safety: the value is only accessed with the lock held
+unsafe impl<T> Sync for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+	/// Create an unlocked lock.
+	pub(crate) const fn new(value: T) -> SpinLock<T> {
+		SpinLock {
+			locked: AtomicBool::new(false),
+			value: UnsafeCell::new(value),
+		}
+	}
+
+	/// Run `f` with the lock held.
+	pub(crate) fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+		while self
+			.locked
+			.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+			.is_err()
+		{
+			core::hint::spin_loop();
+		}
+		// Safety: we hold the lock
+		let result = f(unsafe { &mut *self.value.get() });
+		self.locked.store(false, Ordering::Release);
+		result
+	}
+}
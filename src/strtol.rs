@@ -72,9 +72,46 @@ pub unsafe extern "C" fn strtoumax(
 	strtox(s, endptr, base, 0, c_ulonglong::MAX) as c_ulonglong
 }
 
-pub unsafe fn strtox(
-	s: *const c_char,
-	endptr: *mut *const c_char,
+/// A character type which [`strtox`] can parse.
+///
+/// This is `c_char` for the `strto*` family and `wchar_t` for the `wcsto*`
+/// family.
+pub trait StrtoxChar: Copy {
+	/// Get the value of this character as a byte, or `None` if it lies outside
+	/// the 8-bit range (and so can't be a digit, a sign or whitespace).
+	fn as_byte(self) -> Option<u8>;
+}
+
+macro_rules! impl_strtox_char_narrow {
+	($($t:ty),*) => {
+		$(
+			impl StrtoxChar for $t {
+				fn as_byte(self) -> Option<u8> {
+					Some(self as u8)
+				}
+			}
+		)*
+	};
+}
+
+macro_rules! impl_strtox_char_wide {
+	($($t:ty),*) => {
+		$(
+			impl StrtoxChar for $t {
+				fn as_byte(self) -> Option<u8> {
+					u8::try_from(self).ok()
+				}
+			}
+		)*
+	};
+}
+
+impl_strtox_char_narrow!(i8, u8);
+impl_strtox_char_wide!(u16, i32, u32);
+
+pub unsafe fn strtox<T: StrtoxChar>(
+	s: *const T,
+	endptr: *mut *const T,
 	base: c_int,
 	min: c_longlong,
 	max: c_ulonglong,
@@ -86,29 +123,29 @@ pub unsafe fn strtox(
 
 	// Skip leading whitespace.
 	let mut s = s;
-	while isspace(c_int::from(*s)) != 0 {
+	while (*s).as_byte().is_some_and(|c| isspace(c_int::from(c)) != 0) {
 		s = s.add(1);
 	}
 
 	// Parse an optional +/- sign.
 	let mut negate = false;
-	if *s == b'+' as c_char {
+	if (*s).as_byte() == Some(b'+') {
 		s = s.add(1);
-	} else if *s == b'-' as c_char {
+	} else if (*s).as_byte() == Some(b'-') {
 		negate = true;
 		s = s.add(1);
 	}
 
-	const LITTLE_X: c_char = b'x' as c_char;
-	const BIG_X: c_char = b'X' as c_char;
-	const ZERO: c_char = b'0' as c_char;
+	let is_x = |c: T| matches!(c.as_byte(), Some(b'x' | b'X'));
+	let is_hex = |c: T| c.as_byte().is_some_and(|c| c.is_ascii_hexdigit());
+	let is_zero = |c: T| c.as_byte() == Some(b'0');
 
 	// Parse an optional base prefix.
 	let mut base: c_ulonglong = base as c_ulonglong;
 	if base == 0 {
-		if *s == ZERO {
+		if is_zero(*s) {
 			s = s.add(1);
-			if (*s == LITTLE_X || *s == BIG_X) && (*s.add(1) as u8).is_ascii_hexdigit() {
+			if is_x(*s) && is_hex(*s.add(1)) {
 				s = s.add(1);
 				base = 16;
 			} else {
@@ -117,11 +154,7 @@ pub unsafe fn strtox(
 		} else {
 			base = 10;
 		}
-	} else if base == 16
-		&& *s == ZERO
-		&& (*s.add(1) == LITTLE_X || *s.add(1) == BIG_X)
-		&& (*s.add(2) as u8).is_ascii_hexdigit()
-	{
+	} else if base == 16 && is_zero(*s) && is_x(*s.add(1)) && is_hex(*s.add(2)) {
 		s = s.add(2);
 	}
 
@@ -129,10 +162,10 @@ pub unsafe fn strtox(
 	let mut overflow = false;
 	let mut num: c_ulonglong = 0;
 	loop {
-		let digit: c_ulonglong = match *s as u8 {
-			x @ b'0'..=b'9' => x - b'0',
-			x @ b'a'..=b'z' => x - b'a' + 10,
-			x @ b'A'..=b'Z' => x - b'A' + 10,
+		let digit: c_ulonglong = match (*s).as_byte() {
+			Some(x @ b'0'..=b'9') => x - b'0',
+			Some(x @ b'a'..=b'z') => x - b'a' + 10,
+			Some(x @ b'A'..=b'Z') => x - b'A' + 10,
 			_ => break,
 		}
 		.into();
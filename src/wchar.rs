@@ -0,0 +1,415 @@
+//! Rust implementation of the C library's wide-character string functions
+//! (`wcslen`, `wcscpy`, `wcscmp`, `wmemcpy`, etc).
+//!
+//! Licensed under the Blue Oak Model Licence 1.0.0
+
+use core::ffi::{c_int, c_long, c_longlong, c_uint, c_ulong, c_ulonglong};
+
+use crate::strtol::strtox;
+
+/// The C `wchar_t` type.
+///
+/// This is 16 bits on Windows or with the `short_wchar` feature (which
+/// matches GCC's `-fshort-wchar` option), unsigned 32 bits on Arm and
+/// AArch64 (except on Apple platforms), and signed 32 bits everywhere else,
+/// including RISC-V.
+#[allow(non_camel_case_types)]
+#[cfg(any(windows, feature = "short_wchar"))]
+pub type wchar_t = u16;
+
+/// The C `wchar_t` type.
+///
+/// This is 16 bits on Windows or with the `short_wchar` feature (which
+/// matches GCC's `-fshort-wchar` option), unsigned 32 bits on Arm and
+/// AArch64 (except on Apple platforms), and signed 32 bits everywhere else,
+/// including RISC-V.
+#[allow(non_camel_case_types)]
+#[cfg(all(
+	not(any(windows, feature = "short_wchar", target_vendor = "apple")),
+	any(target_arch = "arm", target_arch = "aarch64")
+))]
+pub type wchar_t = u32;
+
+/// The C `wchar_t` type.
+///
+/// This is 16 bits on Windows or with the `short_wchar` feature (which
+/// matches GCC's `-fshort-wchar` option), unsigned 32 bits on Arm and
+/// AArch64 (except on Apple platforms), and signed 32 bits everywhere else,
+/// including RISC-V.
+#[allow(non_camel_case_types)]
+#[cfg(all(
+	not(any(windows, feature = "short_wchar")),
+	any(
+		target_vendor = "apple",
+		not(any(target_arch = "arm", target_arch = "aarch64"))
+	)
+))]
+pub type wchar_t = i32;
+
+/// The C `wint_t` type - a `wchar_t`, or `WEOF`.
+///
+/// This is 16 bits on Windows, and 32 bits everywhere else (even with the
+/// `short_wchar` feature, as GCC's `-fshort-wchar` leaves it alone).
+#[allow(non_camel_case_types)]
+#[cfg(windows)]
+pub type wint_t = u16;
+
+/// The C `wint_t` type - a `wchar_t`, or `WEOF`.
+///
+/// This is 16 bits on Windows, and 32 bits everywhere else (even with the
+/// `short_wchar` feature, as GCC's `-fshort-wchar` leaves it alone).
+#[allow(non_camel_case_types)]
+#[cfg(not(windows))]
+pub type wint_t = c_uint;
+
+/// The value of `wint_t` which indicates end-of-file.
+pub const WEOF: wint_t = wint_t::MAX;
+
+/// Rust implementation of C library function `wcslen`
+///
+/// Passing NULL (core::ptr::null()) gives undefined behaviour.
+#[cfg_attr(feature = "wcslen", no_mangle)]
+pub unsafe extern "C" fn wcslen(mut s: *const wchar_t) -> usize {
+	let mut result = 0;
+	while *s != 0 {
+		s = s.add(1);
+		result += 1;
+	}
+	result
+}
+
+/// Rust implementation of C library function `wcscpy`
+///
+/// Passing NULL (core::ptr::null()) gives undefined behaviour.
+#[cfg_attr(feature = "wcscpy", no_mangle)]
+pub unsafe extern "C" fn wcscpy(dest: *mut wchar_t, src: *const wchar_t) -> *mut wchar_t {
+	let mut i = 0;
+	loop {
+		let c = *src.add(i);
+		*dest.add(i) = c;
+		if c == 0 {
+			break;
+		}
+		i += 1;
+	}
+	dest
+}
+
+/// Rust implementation of C library function `wcsncpy`
+///
+/// Passing NULL (core::ptr::null()) gives undefined behaviour.
+#[cfg_attr(feature = "wcsncpy", no_mangle)]
+pub unsafe extern "C" fn wcsncpy(
+	dest: *mut wchar_t,
+	src: *const wchar_t,
+	count: usize,
+) -> *mut wchar_t {
+	let mut i = 0;
+	while i < count {
+		let c = *src.add(i);
+		*dest.add(i) = c;
+		i += 1;
+		if c == 0 {
+			break;
+		}
+	}
+	for j in i..count {
+		*dest.add(j) = 0;
+	}
+	dest
+}
+
+/// Rust implementation of C library function `wcscat`
+///
+/// Passing NULL (core::ptr::null()) gives undefined behaviour.
+#[cfg_attr(feature = "wcscat", no_mangle)]
+pub unsafe extern "C" fn wcscat(dest: *mut wchar_t, src: *const wchar_t) -> *mut wchar_t {
+	wcscpy(dest.add(wcslen(dest)), src);
+	dest
+}
+
+/// Rust implementation of C library function `wcscmp`
+///
+/// Characters are compared as `wchar_t` values.
+#[cfg_attr(feature = "wcscmp", no_mangle)]
+pub unsafe extern "C" fn wcscmp(s1: *const wchar_t, s2: *const wchar_t) -> c_int {
+	wcsncmp(s1, s2, usize::MAX)
+}
+
+/// Rust implementation of C library function `wcsncmp`
+///
+/// Characters are compared as `wchar_t` values.
+#[cfg_attr(feature = "wcsncmp", no_mangle)]
+pub unsafe extern "C" fn wcsncmp(s1: *const wchar_t, s2: *const wchar_t, n: usize) -> c_int {
+	for i in 0..n {
+		let c1 = *s1.add(i);
+		let c2 = *s2.add(i);
+		if c1 != c2 || c1 == 0 {
+			return c1.cmp(&c2) as c_int;
+		}
+	}
+	0
+}
+
+/// Rust implementation of C library function `wcschr`
+#[cfg_attr(feature = "wcschr", no_mangle)]
+pub unsafe extern "C" fn wcschr(haystack: *const wchar_t, needle: wchar_t) -> *const wchar_t {
+	for idx in 0.. {
+		let ptr = haystack.add(idx);
+		if needle == *ptr {
+			return ptr;
+		}
+		if *ptr == 0 {
+			break;
+		}
+	}
+	core::ptr::null()
+}
+
+/// Rust implementation of C library function `wcsrchr`
+#[cfg_attr(feature = "wcsrchr", no_mangle)]
+pub unsafe extern "C" fn wcsrchr(haystack: *const wchar_t, needle: wchar_t) -> *const wchar_t {
+	let mut last = core::ptr::null();
+	for idx in 0.. {
+		let ptr = haystack.add(idx);
+		if needle == *ptr {
+			last = ptr;
+		}
+		if *ptr == 0 {
+			break;
+		}
+	}
+	last
+}
+
+/// Rust implementation of C library function `wcsstr`
+#[cfg_attr(feature = "wcsstr", no_mangle)]
+pub unsafe extern "C" fn wcsstr(
+	haystack: *const wchar_t,
+	needle: *const wchar_t,
+) -> *const wchar_t {
+	if *needle == 0 {
+		return haystack;
+	}
+	for haystack_trim in (0..).map(|idx| haystack.add(idx)) {
+		if *haystack_trim == 0 {
+			break;
+		}
+		let mut len = 0;
+		while *needle.add(len) != 0 && *haystack_trim.add(len) == *needle.add(len) {
+			len += 1;
+		}
+		if *needle.add(len) == 0 {
+			return haystack_trim;
+		}
+	}
+	core::ptr::null()
+}
+
+/// Rust implementation of C library function `wcstol`
+#[cfg_attr(feature = "wcstol", no_mangle)]
+pub unsafe extern "C" fn wcstol(
+	s: *const wchar_t,
+	endptr: *mut *const wchar_t,
+	base: c_int,
+) -> c_long {
+	strtox(s, endptr, base, c_long::MIN as _, c_long::MAX as _) as c_long
+}
+
+/// Rust implementation of C library function `wcstoul`
+#[cfg_attr(feature = "wcstoul", no_mangle)]
+pub unsafe extern "C" fn wcstoul(
+	s: *const wchar_t,
+	endptr: *mut *const wchar_t,
+	base: c_int,
+) -> c_ulong {
+	strtox(s, endptr, base, 0, c_ulong::MAX as _) as c_ulong
+}
+
+/// Rust implementation of C library function `wcstoll`
+#[cfg_attr(feature = "wcstoll", no_mangle)]
+pub unsafe extern "C" fn wcstoll(
+	s: *const wchar_t,
+	endptr: *mut *const wchar_t,
+	base: c_int,
+) -> c_longlong {
+	strtox(s, endptr, base, c_longlong::MIN, c_longlong::MAX as _) as c_longlong
+}
+
+/// Rust implementation of C library function `wcstoull`
+#[cfg_attr(feature = "wcstoull", no_mangle)]
+pub unsafe extern "C" fn wcstoull(
+	s: *const wchar_t,
+	endptr: *mut *const wchar_t,
+	base: c_int,
+) -> c_ulonglong {
+	strtox(s, endptr, base, 0, c_ulonglong::MAX)
+}
+
+/// Rust implementation of C library function `wmemchr`
+#[cfg_attr(feature = "wmemchr", no_mangle)]
+pub unsafe extern "C" fn wmemchr(s: *const wchar_t, c: wchar_t, n: usize) -> *const wchar_t {
+	for i in 0..n {
+		if *s.add(i) == c {
+			return s.add(i);
+		}
+	}
+	core::ptr::null()
+}
+
+/// Rust implementation of C library function `wmemcmp`
+///
+/// Characters are compared as `wchar_t` values.
+#[cfg_attr(feature = "wmemcmp", no_mangle)]
+pub unsafe extern "C" fn wmemcmp(s1: *const wchar_t, s2: *const wchar_t, n: usize) -> c_int {
+	for i in 0..n {
+		let c1 = *s1.add(i);
+		let c2 = *s2.add(i);
+		if c1 != c2 {
+			return c1.cmp(&c2) as c_int;
+		}
+	}
+	0
+}
+
+/// Rust implementation of C library function `wmemcpy`
+///
+/// The buffers must not overlap.
+#[cfg_attr(feature = "wmemcpy", no_mangle)]
+pub unsafe extern "C" fn wmemcpy(
+	dest: *mut wchar_t,
+	src: *const wchar_t,
+	n: usize,
+) -> *mut wchar_t {
+	core::ptr::copy_nonoverlapping(src, dest, n);
+	dest
+}
+
+/// Rust implementation of C library function `wmemmove`
+///
+/// The buffers may overlap.
+#[cfg_attr(feature = "wmemmove", no_mangle)]
+pub unsafe extern "C" fn wmemmove(
+	dest: *mut wchar_t,
+	src: *const wchar_t,
+	n: usize,
+) -> *mut wchar_t {
+	core::ptr::copy(src, dest, n);
+	dest
+}
+
+/// Rust implementation of C library function `wmemset`
+#[cfg_attr(feature = "wmemset", no_mangle)]
+pub unsafe extern "C" fn wmemset(dest: *mut wchar_t, c: wchar_t, n: usize) -> *mut wchar_t {
+	for i in 0..n {
+		*dest.add(i) = c;
+	}
+	dest
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	/// Convert a Rust string into a null-terminated wide string
+	fn wide(s: &str) -> Vec<wchar_t> {
+		s.chars().map(|c| c as wchar_t).chain([0]).collect()
+	}
+
+	#[test]
+	fn wcslen_works() {
+		assert_eq!(unsafe { wcslen(wide("").as_ptr()) }, 0);
+		assert_eq!(unsafe { wcslen(wide("Hello").as_ptr()) }, 5);
+		assert_eq!(unsafe { wcslen(wide("\u{263A}").as_ptr()) }, 1);
+	}
+
+	#[test]
+	fn wcscpy_and_wcscat() {
+		let mut dest = [0x55 as wchar_t; 12];
+		unsafe {
+			wcscpy(dest.as_mut_ptr(), wide("hello").as_ptr());
+			wcscat(dest.as_mut_ptr(), wide(" world").as_ptr());
+		}
+		assert_eq!(&dest, wide("hello world").as_slice());
+	}
+
+	#[test]
+	fn wcsncpy_pads() {
+		let mut dest = [0x55 as wchar_t; 6];
+		unsafe { wcsncpy(dest.as_mut_ptr(), wide("hi").as_ptr(), 5) };
+		assert_eq!(dest, [b'h' as wchar_t, b'i' as wchar_t, 0, 0, 0, 0x55]);
+	}
+
+	#[test]
+	fn wcscmp_orders() {
+		unsafe {
+			assert_eq!(wcscmp(wide("Hello").as_ptr(), wide("Hello").as_ptr()), 0);
+			assert!(wcscmp(wide("Hello").as_ptr(), wide("Hello1").as_ptr()) < 0);
+			assert!(wcscmp(wide("Hello1").as_ptr(), wide("Hello").as_ptr()) > 0);
+			assert!(wcscmp(wide("\u{100}").as_ptr(), wide("z").as_ptr()) > 0);
+			assert_eq!(wcsncmp(wide("abc").as_ptr(), wide("abd").as_ptr(), 2), 0);
+		}
+	}
+
+	#[test]
+	fn wcschr_and_wcsrchr() {
+		let haystack = wide("hayyystack");
+		let p = haystack.as_ptr();
+		unsafe {
+			assert_eq!(wcschr(p, b'y' as wchar_t), p.add(2));
+			assert_eq!(wcsrchr(p, b'y' as wchar_t), p.add(4));
+			assert_eq!(wcschr(p, 0), p.add(10));
+			assert_eq!(wcschr(p, b'X' as wchar_t), core::ptr::null());
+			assert_eq!(wcsrchr(p, b'X' as wchar_t), core::ptr::null());
+		}
+	}
+
+	#[test]
+	fn wcsstr_works() {
+		let haystack = wide("haystack");
+		let p = haystack.as_ptr();
+		unsafe {
+			assert_eq!(wcsstr(p, wide("").as_ptr()), p);
+			assert_eq!(wcsstr(p, wide("yst").as_ptr()), p.add(2));
+			assert_eq!(wcsstr(p, wide("stack").as_ptr()), p.add(3));
+			assert_eq!(wcsstr(p, wide("haystacka").as_ptr()), core::ptr::null());
+		}
+	}
+
+	#[test]
+	fn wcstol_works() {
+		let s = wide("  -0x1F rest");
+		let mut end = core::ptr::null();
+		let value = unsafe { wcstol(s.as_ptr(), &mut end, 0) };
+		assert_eq!(value, -0x1F);
+		assert_eq!(end, unsafe { s.as_ptr().add(7) });
+		assert_eq!(
+			unsafe { wcstoull(wide("18446744073709551616").as_ptr(), &mut end, 10) },
+			u64::MAX
+		);
+	}
+
+	#[test]
+	fn wcstol_rejects_wide_lookalikes() {
+		// U+0130 truncates to '0' if treated as a byte
+		let s = wide("\u{130}1");
+		let mut end = core::ptr::null();
+		let value = unsafe { wcstoul(s.as_ptr(), &mut end, 10) };
+		assert_eq!(value, 0);
+		assert_eq!(end, s.as_ptr());
+	}
+
+	#[test]
+	fn wmem_functions() {
+		let mut buf = [0 as wchar_t; 8];
+		unsafe {
+			wmemset(buf.as_mut_ptr(), b'x' as wchar_t, 4);
+			assert_eq!(wmemchr(buf.as_ptr(), 0, 8), buf.as_ptr().add(4));
+			wmemcpy(buf.as_mut_ptr(), wide("ab").as_ptr(), 2);
+			assert_eq!(wmemcmp(buf.as_ptr(), wide("abxx").as_ptr(), 4), 0);
+			wmemmove(buf.as_mut_ptr().add(1), buf.as_ptr(), 4);
+			assert_eq!(wmemcmp(buf.as_ptr(), wide("aabxx").as_ptr(), 5), 0);
+			assert!(wmemcmp(buf.as_ptr(), wide("aabxy").as_ptr(), 5) < 0);
+		}
+	}
+}